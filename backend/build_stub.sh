@@ -0,0 +1,16 @@
+#!/bin/sh
+# Stub toolchain for development and CI, use with:
+#   jielabs_build_worker --command "$(pwd)/build_stub.sh {src} {out}"
+# Fails if any source file contains STUB_FAIL, otherwise writes a fake bitstream
+set -e
+SRC=$1
+OUT=$2
+
+echo "Stub build of:"
+find "$SRC" -type f
+if grep -rq STUB_FAIL "$SRC"; then
+	echo "Error: STUB_FAIL found in source" >&2
+	exit 1
+fi
+echo "stub bitstream" > "$OUT/bitstream.rbf"
+echo "Done"
@@ -0,0 +1 @@
+DROP TABLE io_traces
@@ -0,0 +1,10 @@
+CREATE TABLE io_traces (
+    id SERIAL NOT NULL,
+    user_name TEXT NOT NULL,
+    board TEXT NOT NULL,
+    job_id INTEGER,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    finished_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    events TEXT NOT NULL,
+    PRIMARY KEY (id)
+)
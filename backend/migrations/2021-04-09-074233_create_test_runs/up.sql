@@ -0,0 +1,12 @@
+CREATE TABLE test_runs (
+    id SERIAL NOT NULL,
+    user_name TEXT NOT NULL,
+    job_id INTEGER NOT NULL,
+    board TEXT,
+    script TEXT NOT NULL,
+    passed BOOLEAN,
+    report TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (id)
+)
@@ -0,0 +1,26 @@
+CREATE TABLE assignments (
+    id SERIAL NOT NULL,
+    title TEXT NOT NULL,
+    class TEXT,
+    deadline TIMESTAMP WITH TIME ZONE NOT NULL,
+    top_entity TEXT,
+    ports TEXT,
+    test_script TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE submissions (
+    id SERIAL NOT NULL,
+    assignment_id INTEGER NOT NULL REFERENCES assignments (id) ON DELETE CASCADE,
+    user_name TEXT NOT NULL,
+    job_id INTEGER NOT NULL,
+    submitted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    late BOOLEAN NOT NULL,
+    test_run_id INTEGER,
+    score INTEGER,
+    comment TEXT,
+    graded_by TEXT,
+    graded_at TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (id)
+)
@@ -0,0 +1 @@
+DROP TABLE similarity_reports
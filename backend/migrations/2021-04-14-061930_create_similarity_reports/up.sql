@@ -0,0 +1,10 @@
+CREATE TABLE similarity_reports (
+    id SERIAL NOT NULL,
+    created_by TEXT NOT NULL,
+    assignment_id INTEGER,
+    class TEXT,
+    report TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (id)
+)
@@ -0,0 +1,11 @@
+ALTER TABLE jobs
+    DROP CONSTRAINT jobs_status_check,
+    ALTER COLUMN status DROP NOT NULL,
+    ALTER COLUMN status DROP DEFAULT;
+
+UPDATE jobs SET status = CASE
+    WHEN status = 'success' THEN 'Compilation Success'
+    WHEN status = 'failed' THEN 'Compilation Failed'
+    WHEN status IN ('queued', 'running') THEN NULL
+    ELSE 'System Error'
+END;
@@ -0,0 +1,12 @@
+UPDATE jobs SET status = CASE
+    WHEN status IS NULL AND created_at < CURRENT_TIMESTAMP - INTERVAL '1 day' THEN 'timed_out'
+    WHEN status IS NULL THEN 'queued'
+    WHEN status = 'Compilation Success' THEN 'success'
+    WHEN status = 'Compilation Failed' THEN 'failed'
+    ELSE 'system_error'
+END;
+
+ALTER TABLE jobs
+    ALTER COLUMN status SET DEFAULT 'queued',
+    ALTER COLUMN status SET NOT NULL,
+    ADD CONSTRAINT jobs_status_check CHECK (status IN ('queued', 'running', 'success', 'failed', 'system_error', 'cancelled', 'timed_out'));
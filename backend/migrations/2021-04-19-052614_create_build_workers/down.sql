@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN worker_id;
+
+DROP TABLE build_workers
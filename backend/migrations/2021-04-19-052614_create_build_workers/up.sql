@@ -0,0 +1,11 @@
+CREATE TABLE build_workers (
+    id SERIAL NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    token_hash TEXT NOT NULL UNIQUE,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE jobs ADD COLUMN worker_id INTEGER REFERENCES build_workers(id);
@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN lease_expires_at TIMESTAMP WITH TIME ZONE
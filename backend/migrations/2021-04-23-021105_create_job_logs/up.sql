@@ -0,0 +1,11 @@
+CREATE TABLE job_logs (
+    id SERIAL NOT NULL,
+    job_id INTEGER NOT NULL REFERENCES jobs(id),
+    task_id TEXT NOT NULL,
+    stream TEXT NOT NULL,
+    data TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX job_logs_job_id ON job_logs (job_id);
@@ -0,0 +1,2 @@
+DROP TABLE job_attempts;
+ALTER TABLE jobs DROP COLUMN attempts;
@@ -0,0 +1,16 @@
+ALTER TABLE jobs ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE job_attempts (
+    id SERIAL NOT NULL,
+    job_id INTEGER NOT NULL REFERENCES jobs(id),
+    task_id TEXT NOT NULL,
+    worker_id INTEGER REFERENCES build_workers(id),
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    outcome TEXT,
+    message TEXT,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX job_attempts_job_id ON job_attempts (job_id);
+CREATE INDEX job_attempts_task_id ON job_attempts (task_id);
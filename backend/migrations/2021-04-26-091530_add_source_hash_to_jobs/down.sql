@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN cache_hit;
+ALTER TABLE jobs DROP COLUMN source_hash;
@@ -0,0 +1,4 @@
+ALTER TABLE jobs ADD COLUMN source_hash TEXT;
+ALTER TABLE jobs ADD COLUMN cache_hit BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX jobs_source_hash ON jobs (source_hash);
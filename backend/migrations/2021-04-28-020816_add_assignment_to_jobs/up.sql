@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN assignment_id INTEGER REFERENCES assignments(id)
@@ -0,0 +1 @@
+ALTER TABLE similarity_reports DROP COLUMN error;
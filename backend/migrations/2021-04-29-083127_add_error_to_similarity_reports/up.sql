@@ -0,0 +1 @@
+ALTER TABLE similarity_reports ADD COLUMN error TEXT;
@@ -0,0 +1,622 @@
+use crate::common::{download_s3, err, read_tar_file};
+use crate::job_status::JobStatus;
+use crate::models::*;
+use crate::schema::{assignments, jobs, submissions, test_runs, users};
+use crate::session::get_user;
+use crate::task::start_test_run;
+use crate::test_vector::TestScript;
+use crate::{DbConnection, DbPool};
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Check top entity and assigned ports in quartus constraints
+fn check_constraints(
+    constraints: &str,
+    top_entity: Option<&str>,
+    ports: &[String],
+) -> std::result::Result<(), String> {
+    let mut top = None;
+    let mut assigned = vec![];
+    for line in constraints.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["set_global_assignment", "-name", "TOP_LEVEL_ENTITY", name] => top = Some(*name),
+            ["set_location_assignment", _pin, "-to", sig] => assigned.push(*sig),
+            _ => {}
+        }
+    }
+    if let Some(top_entity) = top_entity {
+        if top != Some(top_entity) {
+            return Err(format!("top entity should be {}", top_entity));
+        }
+    }
+    for port in ports {
+        // vector ports are assigned bit by bit
+        let prefix = format!("{}[", port);
+        if !assigned
+            .iter()
+            .any(|sig| sig == port || sig.starts_with(&prefix))
+        {
+            return Err(format!("port {} is not assigned", port));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct AssignmentInfo {
+    id: i32,
+    title: String,
+    class: Option<String>,
+    deadline: DateTime<Utc>,
+    top_entity: Option<String>,
+    ports: Option<Vec<String>>,
+    test_script: Option<TestScript>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Assignment> for AssignmentInfo {
+    fn from(assignment: Assignment) -> AssignmentInfo {
+        AssignmentInfo {
+            id: assignment.id,
+            title: assignment.title,
+            class: assignment.class,
+            deadline: assignment.deadline,
+            top_entity: assignment.top_entity,
+            ports: assignment
+                .ports
+                .and_then(|ports| serde_json::from_str(&ports).ok()),
+            test_script: assignment
+                .test_script
+                .and_then(|script| serde_json::from_str(&script).ok()),
+            created_at: assignment.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubmissionInfo {
+    id: i32,
+    assignment_id: i32,
+    user_name: String,
+    job_id: i32,
+    submitted_at: DateTime<Utc>,
+    late: bool,
+    test_run_id: Option<i32>,
+    // None if not tested or still running
+    test_passed: Option<bool>,
+    score: Option<i32>,
+    comment: Option<String>,
+    graded_by: Option<String>,
+    graded_at: Option<DateTime<Utc>>,
+}
+
+fn assignment_ports(assignment: &Assignment) -> Vec<String> {
+    assignment
+        .ports
+        .as_ref()
+        .and_then(|ports| serde_json::from_str(ports).ok())
+        .unwrap_or_default()
+}
+
+/// Whether sources submitted to the assignment have constraints to check
+fn needs_source_check(assignment: &Assignment) -> bool {
+    assignment.top_entity.is_some() || !assignment_ports(assignment).is_empty()
+}
+
+/// Check constraints in the source archive against the assignment
+fn check_source(assignment: &Assignment, source: &[u8]) -> std::result::Result<(), String> {
+    let constraints = read_tar_file(source, ".qsf").unwrap_or_default();
+    check_constraints(
+        &constraints,
+        assignment.top_entity.as_deref(),
+        &assignment_ports(assignment),
+    )
+}
+
+fn submission_infos(
+    conn: &DbConnection,
+    submissions: Vec<Submission>,
+) -> QueryResult<Vec<SubmissionInfo>> {
+    let ids: Vec<i32> = submissions
+        .iter()
+        .filter_map(|submission| submission.test_run_id)
+        .collect();
+    let passed: HashMap<i32, Option<bool>> = test_runs::dsl::test_runs
+        .select((test_runs::dsl::id, test_runs::dsl::passed))
+        .filter(test_runs::dsl::id.eq_any(ids))
+        .load::<(i32, Option<bool>)>(conn)?
+        .into_iter()
+        .collect();
+    Ok(submissions
+        .into_iter()
+        .map(|submission| SubmissionInfo {
+            id: submission.id,
+            assignment_id: submission.assignment_id,
+            user_name: submission.user_name,
+            job_id: submission.job_id,
+            submitted_at: submission.submitted_at,
+            late: submission.late,
+            test_run_id: submission.test_run_id,
+            test_passed: submission
+                .test_run_id
+                .and_then(|id| passed.get(&id).cloned().flatten()),
+            score: submission.score,
+            comment: submission.comment,
+            graded_by: submission.graded_by,
+            graded_at: submission.graded_at,
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClassRequest {
+    class: Option<String>,
+}
+
+#[get("/")]
+async fn list(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    query: web::Query<ClassRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        let staff = user.is_staff();
+        let class = if staff {
+            query.into_inner().class
+        } else {
+            // students only see assignments of their class
+            user.class
+        };
+        let assignments = web::block(move || {
+            let mut query = assignments::dsl::assignments
+                .order(assignments::dsl::deadline.asc())
+                .into_boxed();
+            if let Some(class) = class {
+                query = query.filter(
+                    assignments::dsl::class
+                        .eq(class)
+                        .or(assignments::dsl::class.is_null()),
+                );
+            } else if !staff {
+                query = query.filter(assignments::dsl::class.is_null());
+            }
+            query.load::<Assignment>(&conn)
+        })
+        .await
+        .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(
+            assignments
+                .into_iter()
+                .map(AssignmentInfo::from)
+                .collect::<Vec<AssignmentInfo>>(),
+        ));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct AssignmentRequest {
+    title: String,
+    // all classes if None
+    class: Option<String>,
+    deadline: DateTime<Utc>,
+    top_entity: Option<String>,
+    ports: Option<Vec<String>>,
+    test_script: Option<TestScript>,
+}
+
+#[post("/")]
+async fn create(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<AssignmentRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.is_staff() {
+            let body = body.into_inner();
+            if let Some(Err(reason)) = body.test_script.as_ref().map(TestScript::check) {
+                return Ok(HttpResponse::BadRequest().json(reason));
+            }
+            let new_assignment = NewAssignment {
+                title: body.title,
+                class: body.class,
+                deadline: body.deadline,
+                top_entity: body.top_entity,
+                ports: body
+                    .ports
+                    .map(|ports| serde_json::to_string(&ports).unwrap()),
+                test_script: body
+                    .test_script
+                    .map(|script| serde_json::to_string(&script).unwrap()),
+            };
+            let id = web::block(move || {
+                diesel::insert_into(assignments::table)
+                    .values(&new_assignment)
+                    .returning(assignments::dsl::id)
+                    .get_result::<i32>(&conn)
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(id));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[post("/{assignment_id}")]
+async fn update(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    body: web::Json<AssignmentRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.is_staff() {
+            if let Ok(mut assignment) = assignments::dsl::assignments
+                .find(*path)
+                .first::<Assignment>(&conn)
+            {
+                let body = body.into_inner();
+                if let Some(Err(reason)) = body.test_script.as_ref().map(TestScript::check) {
+                    return Ok(HttpResponse::BadRequest().json(reason));
+                }
+                assignment.title = body.title;
+                assignment.class = body.class;
+                assignment.deadline = body.deadline;
+                assignment.top_entity = body.top_entity;
+                assignment.ports = body
+                    .ports
+                    .map(|ports| serde_json::to_string(&ports).unwrap());
+                assignment.test_script = body
+                    .test_script
+                    .map(|script| serde_json::to_string(&script).unwrap());
+                let res = diesel::update(&assignment)
+                    .set(&assignment)
+                    .execute(&conn)
+                    .is_ok();
+                return Ok(HttpResponse::Ok().json(res));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubmitRequest {
+    job_id: i32,
+}
+
+#[post("/{assignment_id}/submit")]
+async fn submit(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    body: web::Json<SubmitRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        let assignment = match assignments::dsl::assignments
+            .find(*path)
+            .first::<Assignment>(&conn)
+        {
+            Ok(assignment) => assignment,
+            Err(_) => return Ok(HttpResponse::NotFound().finish()),
+        };
+        if assignment.class.is_some() && assignment.class != user.class && !user.is_staff() {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+        let job = match jobs::dsl::jobs.find(body.job_id).first::<Job>(&conn) {
+            Ok(job) if job.submitter == user.user_name => job,
+            _ => return Ok(HttpResponse::Forbidden().finish()),
+        };
+        // cancelled or failed to run, nothing to grade
+        if !matches!(job.status, JobStatus::Success | JobStatus::Failed) {
+            return Ok(HttpResponse::BadRequest().json("job is not built"));
+        }
+
+        if needs_source_check(&assignment) {
+            let source = match download_s3(job.source.clone()).await {
+                Some(source) => source,
+                None => return Err(err("fail to download source")),
+            };
+            if let Err(reason) = check_source(&assignment, &source) {
+                return Ok(HttpResponse::BadRequest().json(reason));
+            }
+        }
+
+        // hardware check runs on the board of student, skipped if no board
+        let test_run_id = match assignment
+            .test_script
+            .as_ref()
+            .and_then(|script| serde_json::from_str::<TestScript>(script).ok())
+        {
+            Some(script) => start_test_run(&conn, &user.user_name, job, script).await?,
+            None => None,
+        };
+
+        let new_submission = NewSubmission {
+            assignment_id: assignment.id,
+            user_name: user.user_name,
+            job_id: body.job_id,
+            late: Utc::now() > assignment.deadline,
+            test_run_id,
+        };
+        let submission = diesel::insert_into(submissions::table)
+            .values(&new_submission)
+            .get_result::<Submission>(&conn)
+            .map_err(err)?;
+        let mut infos = submission_infos(&conn, vec![submission]).map_err(err)?;
+        return Ok(HttpResponse::Ok().json(infos.pop()));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[get("/{assignment_id}/submissions")]
+async fn list_submissions(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    query: web::Query<ClassRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        let assignment_id = *path;
+        let class = query.into_inner().class;
+        let infos = web::block(move || {
+            let mut query = submissions::dsl::submissions
+                .filter(submissions::dsl::assignment_id.eq(assignment_id))
+                .order(submissions::dsl::id.desc())
+                .into_boxed();
+            if !user.is_staff() {
+                // students only see their own
+                query = query.filter(submissions::dsl::user_name.eq(user.user_name));
+            } else if let Some(class) = class {
+                let names = users::dsl::users
+                    .select(users::dsl::user_name)
+                    .filter(users::dsl::class.eq(class))
+                    .load::<String>(&conn)?;
+                query = query.filter(submissions::dsl::user_name.eq_any(names));
+            }
+            let submissions = query.load::<Submission>(&conn)?;
+            submission_infos(&conn, submissions)
+        })
+        .await
+        .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(infos));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct GradeRequest {
+    score: Option<i32>,
+    comment: Option<String>,
+}
+
+#[post("/submission/{submission_id}/grade")]
+async fn grade(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    body: web::Json<GradeRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.is_staff() {
+            if let Ok(mut submission) = submissions::dsl::submissions
+                .find(*path)
+                .first::<Submission>(&conn)
+            {
+                let body = body.into_inner();
+                if body.score.is_some() {
+                    submission.score = body.score;
+                }
+                if body.comment.is_some() {
+                    submission.comment = body.comment;
+                }
+                submission.graded_by = Some(user.user_name);
+                submission.graded_at = Some(Utc::now());
+                let res = diesel::update(&submission)
+                    .set(&submission)
+                    .execute(&conn)
+                    .is_ok();
+                return Ok(HttpResponse::Ok().json(res));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct GradebookRequest {
+    class: Option<String>,
+    // json if None
+    format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GradebookRow {
+    user_name: String,
+    student_id: Option<String>,
+    real_name: Option<String>,
+    class: Option<String>,
+    submission_id: Option<i32>,
+    job_id: Option<i32>,
+    build_status: Option<JobStatus>,
+    late: Option<bool>,
+    test_passed: Option<bool>,
+    score: Option<i32>,
+    comment: Option<String>,
+    graded_by: Option<String>,
+    submitted_at: Option<DateTime<Utc>>,
+    built_at: Option<DateTime<Utc>>,
+    graded_at: Option<DateTime<Utc>>,
+}
+
+fn gradebook_rows(
+    conn: &DbConnection,
+    assignment: &Assignment,
+    class: Option<String>,
+) -> QueryResult<Vec<GradebookRow>> {
+    let mut query = users::dsl::users
+        .filter(users::dsl::role.eq("user"))
+        .order(users::dsl::student_id.asc())
+        .into_boxed();
+    if let Some(class) = class.or(assignment.class.clone()) {
+        query = query.filter(users::dsl::class.eq(class));
+    }
+    let students = query.load::<User>(conn)?;
+    let names: Vec<String> = students
+        .iter()
+        .map(|student| student.user_name.clone())
+        .collect();
+    // latest submission of each student counts
+    let mut latest: HashMap<String, SubmissionInfo> = HashMap::new();
+    let submissions = submissions::dsl::submissions
+        .filter(submissions::dsl::assignment_id.eq(assignment.id))
+        .filter(submissions::dsl::user_name.eq_any(&names))
+        .order(submissions::dsl::id.asc())
+        .load::<Submission>(conn)?;
+    for info in submission_infos(conn, submissions)? {
+        latest.insert(info.user_name.clone(), info);
+    }
+    let job_ids: Vec<i32> = latest.values().map(|info| info.job_id).collect();
+    let jobs: HashMap<i32, Job> = jobs::dsl::jobs
+        .filter(jobs::dsl::id.eq_any(job_ids))
+        .load::<Job>(conn)?
+        .into_iter()
+        .map(|job| (job.id, job))
+        .collect();
+    Ok(students
+        .into_iter()
+        .map(|student| {
+            let info = latest.remove(&student.user_name);
+            let job = info.as_ref().and_then(|info| jobs.get(&info.job_id));
+            GradebookRow {
+                user_name: student.user_name,
+                student_id: student.student_id,
+                real_name: student.real_name,
+                class: student.class,
+                submission_id: info.as_ref().map(|info| info.id),
+                job_id: info.as_ref().map(|info| info.job_id),
+                build_status: job.map(|job| job.status),
+                late: info.as_ref().map(|info| info.late),
+                test_passed: info.as_ref().and_then(|info| info.test_passed),
+                score: info.as_ref().and_then(|info| info.score),
+                comment: info.as_ref().and_then(|info| info.comment.clone()),
+                graded_by: info.as_ref().and_then(|info| info.graded_by.clone()),
+                submitted_at: info.as_ref().map(|info| info.submitted_at),
+                built_at: job.and_then(|job| job.finished_at),
+                graded_at: info.as_ref().and_then(|info| info.graded_at),
+            }
+        })
+        .collect())
+}
+
+/// One row per student of the class, with the latest submission
+#[get("/{assignment_id}/gradebook")]
+async fn gradebook(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    query: web::Query<GradebookRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.is_staff() {
+            let assignment_id = *path;
+            let query = query.into_inner();
+            let class = query.class;
+            let rows = web::block(move || {
+                let assignment = assignments::dsl::assignments
+                    .find(assignment_id)
+                    .first::<Assignment>(&conn)?;
+                gradebook_rows(&conn, &assignment, class)
+            })
+            .await
+            .map_err(err)?;
+            if query.format.as_deref() == Some("csv") {
+                let mut wtr = csv::Writer::from_writer(vec![]);
+                for row in &rows {
+                    wtr.serialize(row).map_err(err)?;
+                }
+                let data = wtr.into_inner().map_err(err)?;
+                return Ok(HttpResponse::Ok()
+                    .content_type("text/csv")
+                    .header(
+                        "Content-Disposition",
+                        format!("attachment; filename=\"gradebook_{}.csv\"", assignment_id),
+                    )
+                    .body(data));
+            }
+            return Ok(HttpResponse::Ok().json(rows));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Rerun hardware check of the submission on the board of current user
+#[post("/submission/{submission_id}/check")]
+async fn check(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if let Ok((submission, assignment)) = submissions::table
+            .inner_join(assignments::table)
+            .filter(submissions::dsl::id.eq(*path))
+            .first::<(Submission, Assignment)>(&conn)
+        {
+            if !user.is_staff() && submission.user_name != user.user_name {
+                return Ok(HttpResponse::Forbidden().finish());
+            }
+            let script = assignment
+                .test_script
+                .as_ref()
+                .and_then(|script| serde_json::from_str::<TestScript>(script).ok());
+            let job = jobs::dsl::jobs
+                .find(submission.job_id)
+                .first::<Job>(&conn)
+                .map_err(err)?;
+            if let Some(script) = script {
+                if let Some(id) = start_test_run(&conn, &user.user_name, job, script).await? {
+                    diesel::update(&submission)
+                        .set(submissions::dsl::test_run_id.eq(id))
+                        .execute(&conn)
+                        .map_err(err)?;
+                    return Ok(HttpResponse::Ok().json(id));
+                }
+            }
+            // no script, no board allocated or another test is running
+            return Ok(HttpResponse::Ok().json(false));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+mod test {
+    #[test]
+    fn constraints_match() {
+        use super::*;
+        let constraints = "set_global_assignment -name TOP_LEVEL_ENTITY mod_top\n\
+                           \n# clock <- CLK(0)<0>\nset_location_assignment PIN_A1 -to clock\n\
+                           \n# led[0] -> LED0(1)<1>\nset_location_assignment PIN_A2 -to led[0]\n";
+        let ports = vec![String::from("clock"), String::from("led")];
+        assert_eq!(
+            check_constraints(constraints, Some("mod_top"), &ports),
+            Ok(())
+        );
+        assert!(check_constraints(constraints, Some("top"), &ports).is_err());
+        assert!(check_constraints(constraints, None, &[String::from("reset")]).is_err());
+    }
+}
@@ -5,8 +5,8 @@ use actix_web::cookie::SameSite;
 use actix_web::http::header;
 use actix_web::{middleware, web, App, HttpServer};
 use backend::{
-    board, env::ENV, file, metric, session, task, task_manager, user, ws_board, ws_user,
-    DbConnection,
+    assignment, board, board_manager, env::ENV, file, metric, session, similarity, task,
+    task_manager, trace, user, worker, ws_admin, ws_board, ws_user, DbConnection,
 };
 use diesel::r2d2::{ConnectionManager, Pool};
 use dotenv::dotenv;
@@ -42,6 +42,7 @@ async fn main() -> std::io::Result<()> {
     drop(conn);
 
     task_manager::get_task_manager().do_send(task_manager::SetDb { db: pool.clone() });
+    board_manager::get_board_manager().do_send(board_manager::SetDb { db: pool.clone() });
 
     let secret = ENV.cookie_secret.clone();
     let secret = digest::digest(&digest::SHA512, secret.as_bytes());
@@ -71,12 +72,14 @@ async fn main() -> std::io::Result<()> {
                 web::scope(&ENV.api_root)
                     .service(web::resource("/ws_board").route(web::get().to(ws_board::ws_board)))
                     .service(web::resource("/ws_user").route(web::get().to(ws_user::ws_user)))
+                    .service(web::resource("/ws_admin").route(web::get().to(ws_admin::ws_admin)))
                     .service(
                         web::scope("/user")
                             .service(user::list)
                             .service(user::count)
                             .service(user::update)
                             .service(user::create)
+                            .service(user::get)
                             .service(user::remove),
                     )
                     .service(web::scope("/file").service(file::upload))
@@ -84,17 +87,69 @@ async fn main() -> std::io::Result<()> {
                         web::scope("/board")
                             .service(board::list)
                             .service(board::config_board)
+                            .service(board::maintenance)
+                            .service(board::update_self_test)
+                            .service(board::self_test)
                             .service(board::get_version)
-                            .service(board::update_version),
+                            .service(board::update_version)
+                            .service(board::get_rollout)
+                            .service(board::update_rollout)
+                            .service(board::rollback)
+                            .service(board::audit),
                     )
                     .service(
                         web::scope("/task")
                             .service(task::build)
                             .service(task::finish)
+                            .service(task::report_source)
+                            .service(task::claim)
+                            .service(task::heartbeat)
+                            .service(task::fail)
+                            .service(task::cancel)
+                            .service(
+                                web::resource("/log")
+                                    .app_data(
+                                        web::JsonConfig::default().limit(task::LOG_JSON_LIMIT),
+                                    )
+                                    .route(web::post().to(task::append_log)),
+                            )
+                            .service(task::get_log)
+                            .service(task::list_attempts)
+                            .service(task::get_queue_config)
+                            .service(task::update_queue_config)
                             .service(task::get)
                             .service(task::list)
                             .service(task::count)
-                            .service(task::list_self),
+                            .service(task::list_self)
+                            .service(task::test)
+                            .service(task::get_test),
+                    )
+                    .service(
+                        web::scope("/assignment")
+                            .service(assignment::list)
+                            .service(assignment::create)
+                            .service(assignment::list_submissions)
+                            .service(assignment::gradebook)
+                            .service(assignment::submit)
+                            .service(assignment::grade)
+                            .service(assignment::check)
+                            .service(assignment::update),
+                    )
+                    .service(
+                        web::scope("/similarity")
+                            .service(similarity::start)
+                            .service(similarity::get),
+                    )
+                    .service(
+                        web::scope("/trace")
+                            .service(trace::list_self)
+                            .service(trace::vcd),
+                    )
+                    .service(
+                        web::scope("/worker")
+                            .service(worker::list)
+                            .service(worker::create)
+                            .service(worker::update),
                     )
                     .service(web::scope("/metric").service(metric::get))
                     .service(
@@ -0,0 +1,453 @@
+use backend::build_cache::content_hash;
+use backend::job_status::JobStatus;
+use backend::task::MAX_LOG_CHUNK;
+use backend::task_manager::{SubmitBuildTask, TaskLease};
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use log::*;
+use serde_json::json;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+// names expected by frontend in the result archive
+const BITSTREAM: &str = "bitstream.rbf";
+const STDOUT: &str = "stdout";
+const STDERR: &str = "stderr";
+// well below the limit of backend
+const LOG_CHUNK: usize = MAX_LOG_CHUNK / 2;
+
+#[derive(StructOpt)]
+struct Args {
+    /// API root of backend
+    #[structopt(short, long, default_value = "http://127.0.0.1:8080/api")]
+    backend: String,
+
+    /// Token issued by admin
+    #[structopt(short, long, env = "BUILD_WORKER_TOKEN")]
+    token: String,
+
+    /// Take tasks from "redis" queue or claim them over "http"
+    #[structopt(long, default_value = "http")]
+    transport: String,
+
+    #[structopt(long, default_value = "redis://127.0.0.1/")]
+    redis_url: String,
+
+    #[structopt(long, default_value = "jielabs-waiting")]
+    waiting_queue: String,
+
+    #[structopt(long, default_value = "jielabs-working")]
+    working_queue: String,
+
+    /// Toolchain command run by sh in the source directory, so scripts should
+    /// be given by absolute path. {src} and {out}
+    /// are replaced by source and output directory, bitstream should be
+    /// written to {out}/bitstream.rbf
+    #[structopt(short, long, env = "BUILD_COMMAND")]
+    command: String,
+
+    /// Keep it below BUILD_TIMEOUT_SECS of backend, so that the timeout is
+    /// reported instead of the task being restarted
+    #[structopt(long, default_value = "840")]
+    timeout_secs: u64,
+
+    #[structopt(long, default_value = "20")]
+    heartbeat_secs: u64,
+
+    /// Interval of streaming build log to backend
+    #[structopt(long, default_value = "1")]
+    log_secs: u64,
+
+    #[structopt(long, default_value = "/tmp/jielabs-build")]
+    work_dir: PathBuf,
+}
+
+struct Task {
+    task_id: String,
+    src: String,
+    dst: String,
+}
+
+struct Worker {
+    args: Args,
+    client: reqwest::Client,
+    // set when using redis transport
+    redis: Option<redis::Client>,
+    conn: Option<redis::Connection>,
+}
+
+/// Split output into chunks of at most `limit` bytes without breaking utf-8
+/// sequences
+fn split_utf8(mut bytes: &[u8], limit: usize) -> Vec<&[u8]> {
+    let mut res = vec![];
+    while !bytes.is_empty() {
+        let mut end = bytes.len().min(limit);
+        let is_continuation = |i: usize| i < bytes.len() && bytes[i] & 0xC0 == 0x80;
+        // move back to the start of a character
+        let mut back = end;
+        while back > 0 && is_continuation(back) {
+            back -= 1;
+        }
+        if back > 0 {
+            end = back;
+        } else {
+            // character longer than limit, take it whole
+            while is_continuation(end) {
+                end += 1;
+            }
+        }
+        res.push(&bytes[..end]);
+        bytes = &bytes[end..];
+    }
+    res
+}
+
+fn run(command: &mut Command) -> Result<(), failure::Error> {
+    let status = command.status()?;
+    if !status.success() {
+        failure::bail!("{:?} exited with {}", command, status);
+    }
+    Ok(())
+}
+
+impl Worker {
+    async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> Result<T, failure::Error> {
+        Ok(self
+            .client
+            .post(&format!("{}{}", self.args.backend, path))
+            .bearer_auth(&self.args.token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await?)
+    }
+
+    async fn next_task(&mut self) -> Result<Option<Task>, failure::Error> {
+        if let Some(client) = &self.redis {
+            let conn = if let Some(conn) = &mut self.conn {
+                conn
+            } else {
+                let conn = client.get_connection()?;
+                self.conn = Some(conn);
+                self.conn.as_mut().unwrap() // safe unwrap
+            };
+            // blocks for a few seconds at most
+            let task: Option<String> = redis::cmd("BRPOPLPUSH")
+                .arg(&self.args.waiting_queue)
+                .arg(&self.args.working_queue)
+                .arg(5)
+                .query(conn)?;
+            return Ok(match task {
+                Some(task) => {
+                    let task: SubmitBuildTask = serde_json::from_str(&task)?;
+                    Some(Task {
+                        task_id: task.id,
+                        src: task.src,
+                        dst: task.dst,
+                    })
+                }
+                None => None,
+            });
+        }
+        let lease: Option<TaskLease> = self.post("/task/claim", json!({})).await?;
+        Ok(lease.map(|lease| Task {
+            task_id: lease.task_id,
+            src: lease.src,
+            dst: lease.dst,
+        }))
+    }
+
+    /// Returns false if lease is lost, or the task is finished or taken by
+    /// others when using redis transport
+    async fn heartbeat(&self, task: &Task) -> Result<bool, failure::Error> {
+        let expires: Option<DateTime<Utc>> = self
+            .post("/task/heartbeat", json!({ "task_id": task.task_id }))
+            .await?;
+        Ok(expires.is_some())
+    }
+
+    /// Returns true if backend reuses the result of an identical source, so
+    /// the build can be skipped
+    async fn reuse_result(&self, task: &Task, source: &[u8]) -> bool {
+        let res: Result<bool, failure::Error> = self
+            .post(
+                "/task/source",
+                json!({ "task_id": task.task_id, "content_hash": content_hash(source) }),
+            )
+            .await;
+        res.unwrap_or_else(|err| {
+            // build it anyway
+            warn!("Error occurred when checking build cache: {}", err);
+            false
+        })
+    }
+
+    /// Send output written since last time and advance offsets
+    async fn stream_log(&self, task: &Task, output_dir: &Path, offsets: &mut [usize; 2]) {
+        for (stream, offset) in [STDOUT, STDERR].iter().zip(offsets.iter_mut()) {
+            let content = match fs::read(output_dir.join(stream)) {
+                Ok(content) if content.len() > *offset => content,
+                _ => continue,
+            };
+            let mut rest = &content[*offset..];
+            // keep incomplete utf-8 sequence at the end for next time
+            let valid = match std::str::from_utf8(rest) {
+                Ok(_) => rest.len(),
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(_) => rest.len(),
+            };
+            rest = &rest[..valid];
+            for chunk in split_utf8(rest, LOG_CHUNK) {
+                let data = String::from_utf8_lossy(chunk);
+                let res: Result<bool, failure::Error> = self
+                    .post(
+                        "/task/log",
+                        json!({ "task_id": task.task_id, "stream": stream, "data": data }),
+                    )
+                    .await;
+                if let Err(err) = res {
+                    let refused = err
+                        .downcast_ref::<reqwest::Error>()
+                        .and_then(|err| err.status())
+                        .map(|status| status.is_client_error())
+                        .unwrap_or(false);
+                    if !refused {
+                        // log is not essential, try again next time
+                        warn!("Error occurred when sending log: {}", err);
+                        return;
+                    }
+                    // sending it again would not help, skip this chunk
+                    warn!(
+                        "backend refused log chunk of task {}: {}",
+                        task.task_id, err
+                    );
+                }
+                *offset += chunk.len();
+            }
+        }
+    }
+
+    /// Run toolchain and upload result, returns None if the task is taken away
+    /// or finished with cached result
+    async fn build(&self, task: &Task, dir: &Path) -> Result<Option<JobStatus>, failure::Error> {
+        let source_dir = dir.join("source");
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&source_dir)?;
+        fs::create_dir_all(&output_dir)?;
+
+        // source is a plain tar archive uploaded by frontend
+        let source = self
+            .client
+            .get(&task.src)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        if self.reuse_result(task, &source).await {
+            info!("task {} reuses result of identical source", task.task_id);
+            return Ok(None);
+        }
+        fs::write(dir.join("source.tar"), &source)?;
+        run(Command::new("tar")
+            .arg("-xf")
+            .arg(dir.join("source.tar"))
+            .arg("-C")
+            .arg(&source_dir))?;
+
+        let command = self
+            .args
+            .command
+            .replace("{src}", &source_dir.to_string_lossy())
+            .replace("{out}", &output_dir.to_string_lossy());
+        info!("running {}", command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .current_dir(&source_dir)
+            .stdout(File::create(output_dir.join(STDOUT))?)
+            .stderr(File::create(output_dir.join(STDERR))?)
+            .spawn()?;
+        let started = Instant::now();
+        let mut last_heartbeat = Instant::now();
+        let mut last_log = Instant::now();
+        let mut offsets = [0, 0];
+        let exit = loop {
+            if last_log.elapsed() > Duration::from_secs(self.args.log_secs) {
+                last_log = Instant::now();
+                self.stream_log(task, &output_dir, &mut offsets).await;
+            }
+            if let Some(exit) = child.try_wait()? {
+                break Some(exit);
+            }
+            if started.elapsed() > Duration::from_secs(self.args.timeout_secs) {
+                child.kill().ok();
+                child.wait()?;
+                break None;
+            }
+            if last_heartbeat.elapsed() > Duration::from_secs(self.args.heartbeat_secs) {
+                last_heartbeat = Instant::now();
+                match self.heartbeat(task).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("lease of task {} is lost, aborting", task.task_id);
+                        child.kill().ok();
+                        child.wait()?;
+                        return Ok(None);
+                    }
+                    // lease is long enough to retry later
+                    Err(err) => warn!("Error occurred in heartbeat: {}", err),
+                }
+            }
+            actix_rt::time::delay_for(Duration::from_millis(500)).await;
+        };
+
+        let has_bitstream = output_dir.join(BITSTREAM).is_file();
+        let status = match exit {
+            Some(exit) if exit.success() && has_bitstream => JobStatus::Success,
+            Some(_) => JobStatus::Failed,
+            None => {
+                fs::OpenOptions::new()
+                    .append(true)
+                    .open(output_dir.join(STDERR))
+                    .and_then(|mut file| {
+                        use std::io::Write;
+                        writeln!(file, "Build timed out after {}s", self.args.timeout_secs)
+                    })?;
+                JobStatus::TimedOut
+            }
+        };
+        self.stream_log(task, &output_dir, &mut offsets).await;
+
+        let mut pack = Command::new("tar");
+        pack.arg("-czf")
+            .arg(dir.join("result.tar.gz"))
+            .arg("-C")
+            .arg(&output_dir)
+            .arg(STDOUT)
+            .arg(STDERR);
+        if has_bitstream {
+            pack.arg(BITSTREAM);
+        }
+        run(&mut pack)?;
+        self.client
+            .put(&task.dst)
+            .body(fs::read(dir.join("result.tar.gz"))?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Some(status))
+    }
+
+    async fn process(&self, task: &Task) -> Result<(), failure::Error> {
+        // let backend know who takes the task before spending time on it
+        if let Ok(false) = self.heartbeat(task).await {
+            warn!("task {} is no longer available, skipping", task.task_id);
+            return Ok(());
+        }
+        let dir = self.args.work_dir.join(&task.task_id);
+        let res = self.build(task, &dir).await;
+        fs::remove_dir_all(&dir).ok();
+        match res {
+            Ok(Some(status)) => {
+                info!("task {} finished: {}", task.task_id, status.as_str());
+                let _: bool = self
+                    .post(
+                        "/task/finish",
+                        json!({ "task_id": task.task_id, "status": status.as_str() }),
+                    )
+                    .await?;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!("task {} failed: {}", task.task_id, err);
+                if self.redis.is_some() {
+                    // nobody else would pick it up again
+                    let _: bool = self
+                        .post(
+                            "/task/finish",
+                            json!({
+                                "task_id": task.task_id,
+                                "status": JobStatus::SystemError.as_str()
+                            }),
+                        )
+                        .await?;
+                } else {
+                    let _: bool = self
+                        .post(
+                            "/task/fail",
+                            json!({ "task_id": task.task_id, "message": err.to_string() }),
+                        )
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[actix_rt::main]
+async fn main() -> Result<(), failure::Error> {
+    dotenv().ok();
+    env_logger::init();
+    let args = Args::from_args();
+    let redis = if args.transport == "redis" {
+        Some(redis::Client::open(args.redis_url.clone())?)
+    } else {
+        None
+    };
+    let mut worker = Worker {
+        args,
+        client: reqwest::Client::new(),
+        redis,
+        conn: None,
+    };
+    info!("build worker is up");
+    loop {
+        match worker.next_task().await {
+            Ok(Some(task)) => {
+                info!("got task {}", task.task_id);
+                if let Err(err) = worker.process(&task).await {
+                    warn!(
+                        "Error occurred when reporting task {}: {}",
+                        task.task_id, err
+                    );
+                }
+            }
+            Ok(None) => {
+                if worker.redis.is_none() {
+                    actix_rt::time::delay_for(Duration::from_secs(5)).await;
+                }
+            }
+            Err(err) => {
+                warn!("Error occurred when fetching task: {}", err);
+                // reconnect next time
+                worker.conn = None;
+                actix_rt::time::delay_for(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn split_on_char_boundary() {
+        use super::*;
+        let text = "构建ab";
+        let chunks = split_utf8(text.as_bytes(), 4);
+        // the second character is not split
+        assert_eq!(chunks, vec!["构".as_bytes(), "建a".as_bytes(), b"b"]);
+        for chunk in split_utf8(text.as_bytes(), 1) {
+            assert!(std::str::from_utf8(chunk).is_ok());
+        }
+    }
+}
@@ -22,7 +22,7 @@ fn main(args: Args) {
         .filter(backend::schema::jobs::dsl::id.eq(args.id))
         .first::<backend::models::Job>(&db_conn)
         .unwrap();
-    if job.status.is_none() {
+    if !job.status.is_finished() {
         let src_url = backend::common::get_download_url(&job.source);
         let dst_url = backend::common::get_upload_url(&job.destination.unwrap());
         let req = backend::task_manager::SubmitBuildTask {
@@ -30,6 +30,7 @@ fn main(args: Args) {
             src: src_url,
             dst: dst_url,
             timestamp: backend::common::get_timestamp(),
+            priority: job.priority,
         };
         redis::cmd("LPUSH")
             .arg(&backend::env::ENV.redis_waiting_queue)
@@ -66,6 +66,64 @@ fn main(args: Args) {
                         ws_user::WSUserMessageS2U::ProgramBitstreamFinish(result) => {
                             println!("Program bitstream finished with {}", result);
                         }
+                        ws_user::WSUserMessageS2U::Kicked(reason) => {
+                            println!("Kicked: {}", reason);
+                        }
+                        ws_user::WSUserMessageS2U::WatchResult(res) => {
+                            println!("Watch result: {:?}", res);
+                        }
+                        ws_user::WSUserMessageS2U::SpectatorJoined(user) => {
+                            println!("Spectator {} joined", user);
+                        }
+                        ws_user::WSUserMessageS2U::SpectatorLeft(user) => {
+                            println!("Spectator {} left", user);
+                        }
+                        ws_user::WSUserMessageS2U::SessionInvitation(owner) => {
+                            println!("Invited by {}", owner);
+                        }
+                        ws_user::WSUserMessageS2U::MemberJoined(user) => {
+                            println!("Member {} joined", user);
+                        }
+                        ws_user::WSUserMessageS2U::MemberLeft(user) => {
+                            println!("Member {} left", user);
+                        }
+                        ws_user::WSUserMessageS2U::ControlChanged(user) => {
+                            println!("Control token holder: {:?}", user);
+                        }
+                        ws_user::WSUserMessageS2U::TakeoverRequested(user) => {
+                            println!("Takeover requested by {}", user);
+                        }
+                        ws_user::WSUserMessageS2U::TakeoverResult(res) => {
+                            println!("Takeover result: {:?}", res);
+                        }
+                        ws_user::WSUserMessageS2U::TakeoverChanged(user) => {
+                            println!("Board taken over by {:?}", user);
+                        }
+                        ws_user::WSUserMessageS2U::HelpStatus(entry) => {
+                            println!("Help status: {:?}", entry);
+                        }
+                        ws_user::WSUserMessageS2U::HelpQueue(entries) => {
+                            println!("Help queue: {:?}", entries);
+                        }
+                        ws_user::WSUserMessageS2U::IORecording(enabled) => {
+                            println!("IO recording: {}", enabled);
+                        }
+                        ws_user::WSUserMessageS2U::IOTraceSaved(id) => {
+                            println!("IO trace saved: {}", id);
+                        }
+                        ws_user::WSUserMessageS2U::TestRunFinished { id, report } => {
+                            println!("Test run {} finished: {:?}", id, report);
+                        }
+                        ws_user::WSUserMessageS2U::JobUpdate(info) => {
+                            println!("Job {} is {:?}", info.id, info.state);
+                        }
+                        ws_user::WSUserMessageS2U::JobLog {
+                            job_id,
+                            stream,
+                            data,
+                        } => {
+                            print!("Job {} {}: {}", job_id, stream, data);
+                        }
                     }
                 }
             }
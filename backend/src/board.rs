@@ -1,22 +1,61 @@
-use crate::board_manager::{get_board_manager, GetBoardList, SendToBoardByRemote};
-use crate::common::err;
+use crate::board_manager::{
+    get_board_manager, BoardInfo2, GetBoardList, SendToBoardByRemote, SetBoardMaintenance,
+    StartSelfTest,
+};
+use crate::common::{download_s3, err};
+use crate::firmware::{load_rollout, save_rollout, Channel, FirmwareRelease};
+use crate::models::AuditLog;
+use crate::schema::audit_logs;
 use crate::schema::configs;
+use crate::self_test::SelfTestConfig;
 use crate::session::get_user;
-use crate::ws_board::WSBoardMessageS2B;
+use crate::ws_board::{board_remote, WSBoardMessageS2B};
 use crate::DbPool;
 use actix_session::Session;
-use actix_web::{get, post, web, HttpResponse, Result};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Result};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct BoardListItem {
+    #[serde(flatten)]
+    info: BoardInfo2,
+    channel: Channel,
+    expected_version: Option<String>,
+    outdated: bool,
+}
 
 #[get("/list")]
 async fn list(sess: Session, pool: web::Data<DbPool>) -> Result<HttpResponse> {
     let conn = pool.get().map_err(err)?;
-    if let (Some(user), _conn) = get_user(&sess, conn).await? {
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
         if user.role == "admin" {
+            let rollout = web::block(move || load_rollout(&conn)).await.map_err(err)?;
             let man = get_board_manager();
             if let Ok(res) = man.send(GetBoardList).await {
-                return Ok(HttpResponse::Ok().json(res.0));
+                let boards: Vec<BoardListItem> = res
+                    .0
+                    .into_iter()
+                    .map(|info| {
+                        let remote = &info.board.remote;
+                        let expected_version = rollout
+                            .release_for(remote)
+                            .map(|release| release.version.clone());
+                        let outdated = match &expected_version {
+                            Some(version) => *version != info.board.software_version,
+                            None => false,
+                        };
+                        BoardListItem {
+                            channel: rollout.channel_for(remote),
+                            expected_version,
+                            outdated,
+                            info,
+                        }
+                    })
+                    .collect();
+                return Ok(HttpResponse::Ok().json(boards));
             }
         }
     }
@@ -28,6 +67,7 @@ struct UpdateVersionRequest {
     version: String,
     url: String,
     hash: String,
+    channel: Option<Channel>,
 }
 
 #[post("/version")]
@@ -39,18 +79,19 @@ async fn update_version(
     let conn = pool.get().map_err(err)?;
     if let (Some(user), conn) = get_user(&sess, conn).await? {
         if user.role == "admin" {
-            let body = serde_json::to_string(&*body)?;
+            let body = body.into_inner();
+            let channel = body.channel.unwrap_or(Channel::Stable);
+            let release = FirmwareRelease {
+                version: body.version,
+                url: body.url,
+                hash: body.hash,
+            };
             web::block(move || {
-                let kv = (
-                    configs::dsl::key.eq("version"),
-                    configs::dsl::value.eq(&body),
-                );
-                diesel::insert_into(configs::table)
-                    .values(kv)
-                    .on_conflict(configs::dsl::key)
-                    .do_update()
-                    .set(kv)
-                    .execute(&conn)
+                conn.transaction(|| {
+                    let mut rollout = load_rollout(&conn)?;
+                    rollout.publish(channel, release);
+                    save_rollout(&conn, &rollout)
+                })
             })
             .await
             .map_err(err)?;
@@ -60,29 +101,124 @@ async fn update_version(
     Ok(HttpResponse::Forbidden().finish())
 }
 
+#[derive(Serialize, Deserialize)]
+struct VersionRequest {
+    // same as in authentication, boards without it are identified by ip
+    serial: Option<String>,
+}
+
 #[get("/version")]
-async fn get_version(pool: web::Data<DbPool>) -> Result<HttpResponse> {
+async fn get_version(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<VersionRequest>,
+) -> Result<HttpResponse> {
+    let remote = match &query.serial {
+        Some(serial) if !serial.is_empty() => serial.clone(),
+        _ => board_remote(&req),
+    };
     let conn = pool.get().map_err(err)?;
-    let config = web::block(move || {
-        configs::dsl::configs
-            .select(configs::dsl::value)
-            .filter(configs::dsl::key.eq("version"))
-            .first::<Option<String>>(&conn)
-            .optional()
-    })
-    .await
-    .map_err(err)?;
-    if let Some(res) = config {
-        if let Some(body) = res {
-            let info: UpdateVersionRequest = serde_json::from_str(&body)?;
-            let body = format!("{}\n{}\n{}\n", info.version, info.url, info.hash);
-            return Ok(HttpResponse::Ok().body(&body));
+    let rollout = web::block(move || load_rollout(&conn)).await.map_err(err)?;
+    if let Some(info) = rollout.release_for(&remote) {
+        let body = format!("{}\n{}\n{}\n", info.version, info.url, info.hash);
+        return Ok(HttpResponse::Ok().body(&body));
+    }
+    // unset
+    Ok(HttpResponse::Ok().body("\n\n\n"))
+}
+
+#[get("/rollout")]
+async fn get_rollout(sess: Session, pool: web::Data<DbPool>) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let rollout = web::block(move || load_rollout(&conn)).await.map_err(err)?;
+            return Ok(HttpResponse::Ok().json(rollout));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct UpdateRolloutRequest {
+    canary_percentage: Option<u8>,
+    // board remote -> channel, null to unassign
+    assignments: Option<HashMap<String, Option<Channel>>>,
+    paused: Option<bool>,
+}
+
+#[post("/rollout")]
+async fn update_rollout(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<UpdateRolloutRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let body = body.into_inner();
+            if body.canary_percentage.map(|p| p > 100).unwrap_or(false) {
+                return Ok(HttpResponse::BadRequest().finish());
+            }
+            web::block(move || {
+                conn.transaction(|| {
+                    let mut rollout = load_rollout(&conn)?;
+                    if let Some(canary_percentage) = body.canary_percentage {
+                        rollout.canary_percentage = canary_percentage;
+                    }
+                    if let Some(assignments) = body.assignments {
+                        for (remote, channel) in assignments {
+                            match channel {
+                                Some(channel) => rollout.assignments.insert(remote, channel),
+                                None => rollout.assignments.remove(&remote),
+                            };
+                        }
+                    }
+                    if let Some(paused) = body.paused {
+                        rollout.paused = paused;
+                    }
+                    save_rollout(&conn, &rollout)
+                })
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(true));
         }
-    } else {
-        // unset
-        return Ok(HttpResponse::Ok().body("\n\n\n"));
     }
+    Ok(HttpResponse::Forbidden().finish())
+}
 
+#[derive(Serialize, Deserialize)]
+struct RollbackRequest {
+    channel: Channel,
+}
+
+#[post("/rollout/rollback")]
+async fn rollback(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<RollbackRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let channel = body.channel;
+            let res = web::block(move || {
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let mut rollout = load_rollout(&conn)?;
+                    if rollout.rollback(channel) {
+                        save_rollout(&conn, &rollout)?;
+                        Ok(true)
+                    } else {
+                        Ok(false)
+                    }
+                })
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(res));
+        }
+    }
     Ok(HttpResponse::Forbidden().finish())
 }
 
@@ -113,3 +249,167 @@ async fn config_board(
     }
     Ok(HttpResponse::Forbidden().finish())
 }
+
+#[derive(Serialize, Deserialize)]
+struct MaintenanceRequest {
+    board: String,
+    maintenance: bool,
+}
+
+#[post("/maintenance")]
+async fn maintenance(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<MaintenanceRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), _conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let res = get_board_manager()
+                .send(SetBoardMaintenance {
+                    remote: body.board.clone(),
+                    maintenance: body.maintenance,
+                })
+                .await
+                .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(res));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[post("/self_test/config")]
+async fn update_self_test(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<SelfTestConfig>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let body = serde_json::to_string(&*body)?;
+            web::block(move || {
+                let kv = (
+                    configs::dsl::key.eq("self_test"),
+                    configs::dsl::value.eq(&body),
+                );
+                diesel::insert_into(configs::table)
+                    .values(kv)
+                    .on_conflict(configs::dsl::key)
+                    .do_update()
+                    .set(kv)
+                    .execute(&conn)
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(true));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SelfTestRequest {
+    board: String,
+}
+
+#[post("/self_test")]
+async fn self_test(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<SelfTestRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let config = web::block(move || {
+                configs::dsl::configs
+                    .select(configs::dsl::value)
+                    .filter(configs::dsl::key.eq("self_test"))
+                    .first::<Option<String>>(&conn)
+                    .optional()
+            })
+            .await
+            .map_err(err)?;
+            if let Some(Some(config)) = config {
+                let config: SelfTestConfig = serde_json::from_str(&config)?;
+                if let Some(bitstream) = download_s3(config.bitstream.clone()).await {
+                    let res = get_board_manager()
+                        .send(StartSelfTest {
+                            remote: body.board.clone(),
+                            config,
+                            bitstream,
+                        })
+                        .await
+                        .map_err(err)?;
+                    return Ok(HttpResponse::Ok().json(res));
+                }
+            }
+            // self test unconfigured or bitstream missing
+            return Ok(HttpResponse::Ok().json(false));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditListRequest {
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditInfo {
+    id: i32,
+    created_at: DateTime<Utc>,
+    actor: String,
+    action: String,
+    target: String,
+    detail: Option<String>,
+}
+
+impl From<AuditLog> for AuditInfo {
+    fn from(log: AuditLog) -> AuditInfo {
+        AuditInfo {
+            id: log.id,
+            created_at: log.created_at,
+            actor: log.actor,
+            action: log.action,
+            target: log.target,
+            detail: log.detail,
+        }
+    }
+}
+
+#[get("/audit")]
+async fn audit(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    query: web::Query<AuditListRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let offset = query.offset.unwrap_or(0);
+            let limit = query.limit.unwrap_or(5);
+            let logs = web::block(move || {
+                let query = audit_logs::dsl::audit_logs
+                    .order(audit_logs::dsl::id.desc())
+                    .offset(offset);
+                if limit >= 0 {
+                    query.limit(limit).load::<AuditLog>(&conn)
+                } else {
+                    query.load::<AuditLog>(&conn)
+                }
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(
+                logs.into_iter()
+                    .map(AuditInfo::from)
+                    .collect::<Vec<AuditInfo>>(),
+            ));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
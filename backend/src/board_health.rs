@@ -0,0 +1,185 @@
+use crate::env::ENV;
+use crate::self_test::SelfTestResult;
+use chrono::{DateTime, Utc};
+use serde_derive::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const RTT_HISTORY_LEN: usize = 16;
+// reconnects are counted in this window, health of boards gone for longer is
+// dropped
+const RECONNECT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Health data of one board, kept across reconnects of the same remote
+pub struct BoardHealth {
+    connected_at: DateTime<Utc>,
+    reconnect_count: u64,
+    recent_reconnects: VecDeque<Instant>,
+    rtt_history: VecDeque<Duration>,
+    program_success: u64,
+    program_failure: u64,
+    io_updates: u64,
+    io_window_start: Instant,
+    io_update_rate: f64,
+    last_seen: Instant,
+    pub maintenance: Option<String>,
+    pub self_test: Option<SelfTestResult>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BoardHealthInfo {
+    pub connected_at: DateTime<Utc>,
+    pub uptime: i64,
+    pub reconnect_count: u64,
+    pub rtt_ms: Vec<u64>,
+    pub avg_rtt_ms: Option<u64>,
+    pub program_success: u64,
+    pub program_failure: u64,
+    pub program_success_ratio: Option<f64>,
+    pub io_update_rate: f64,
+    pub maintenance: Option<String>,
+    pub self_test: Option<SelfTestResult>,
+}
+
+impl BoardHealth {
+    pub fn new() -> Self {
+        Self {
+            connected_at: Utc::now(),
+            reconnect_count: 0,
+            recent_reconnects: VecDeque::new(),
+            rtt_history: VecDeque::new(),
+            program_success: 0,
+            program_failure: 0,
+            io_updates: 0,
+            io_window_start: Instant::now(),
+            io_update_rate: 0.0,
+            last_seen: Instant::now(),
+            maintenance: None,
+            self_test: None,
+        }
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.connected_at = Utc::now();
+        self.reconnect_count += 1;
+        self.recent_reconnects.push_back(Instant::now());
+    }
+
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        if self.rtt_history.len() >= RTT_HISTORY_LEN {
+            self.rtt_history.pop_front();
+        }
+        self.rtt_history.push_back(rtt);
+    }
+
+    pub fn record_program(&mut self, success: bool) {
+        if success {
+            self.program_success += 1;
+        } else {
+            self.program_failure += 1;
+        }
+    }
+
+    pub fn record_io_update(&mut self) {
+        self.io_updates += 1;
+    }
+
+    /// Called periodically to roll io update rate and forget old reconnects
+    pub fn tick(&mut self, connected: bool) {
+        let now = Instant::now();
+        if connected {
+            self.last_seen = now;
+        }
+        let elapsed = now.duration_since(self.io_window_start).as_secs_f64();
+        if elapsed > 0.0 {
+            self.io_update_rate = self.io_updates as f64 / elapsed;
+        }
+        self.io_updates = 0;
+        self.io_window_start = now;
+        while let Some(time) = self.recent_reconnects.front() {
+            if now.duration_since(*time) > RECONNECT_WINDOW {
+                self.recent_reconnects.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Board has not been connected for the whole reconnect window
+    pub fn expired(&self) -> bool {
+        self.last_seen.elapsed() > RECONNECT_WINDOW
+    }
+
+    /// Forget error counters, e.g. when admin puts board back to service
+    pub fn reset_errors(&mut self) {
+        self.program_success = 0;
+        self.program_failure = 0;
+        self.recent_reconnects.clear();
+        self.rtt_history.clear();
+    }
+
+    pub fn avg_rtt(&self) -> Option<Duration> {
+        if self.rtt_history.is_empty() {
+            None
+        } else {
+            Some(self.rtt_history.iter().sum::<Duration>() / self.rtt_history.len() as u32)
+        }
+    }
+
+    pub fn program_success_ratio(&self) -> Option<f64> {
+        let total = self.program_success + self.program_failure;
+        if total == 0 {
+            None
+        } else {
+            Some(self.program_success as f64 / total as f64)
+        }
+    }
+
+    /// Returns the reason if error rates cross configured thresholds
+    pub fn check_fault(&self) -> Option<String> {
+        let attempts = self.program_success + self.program_failure;
+        if attempts >= ENV.board_min_program_attempts {
+            let failure_ratio = self.program_failure as f64 / attempts as f64;
+            if failure_ratio > ENV.board_max_program_failure_ratio {
+                return Some(format!(
+                    "program failure ratio {:.2} in {} attempts",
+                    failure_ratio, attempts
+                ));
+            }
+        }
+        if self.recent_reconnects.len() as u64 > ENV.board_max_reconnects_per_hour {
+            return Some(format!(
+                "{} reconnects in last hour",
+                self.recent_reconnects.len()
+            ));
+        }
+        if self.rtt_history.len() >= RTT_HISTORY_LEN / 2 {
+            if let Some(rtt) = self.avg_rtt() {
+                if rtt > Duration::from_millis(ENV.board_max_rtt_ms) {
+                    return Some(format!("average rtt {}ms", rtt.as_millis()));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn info(&self) -> BoardHealthInfo {
+        BoardHealthInfo {
+            connected_at: self.connected_at,
+            uptime: (Utc::now() - self.connected_at).num_seconds(),
+            reconnect_count: self.reconnect_count,
+            rtt_ms: self
+                .rtt_history
+                .iter()
+                .map(|rtt| rtt.as_millis() as u64)
+                .collect(),
+            avg_rtt_ms: self.avg_rtt().map(|rtt| rtt.as_millis() as u64),
+            program_success: self.program_success,
+            program_failure: self.program_failure,
+            program_success_ratio: self.program_success_ratio(),
+            io_update_rate: self.io_update_rate,
+            maintenance: self.maintenance.clone(),
+            self_test: self.self_test.clone(),
+        }
+    }
+}
@@ -1,21 +1,32 @@
+use crate::board_health::{BoardHealth, BoardHealthInfo};
+use crate::common::generate_uuid;
+use crate::io_trace::{IOTraceKind, IOTraceRecording};
+use crate::models::{NewAuditLog, NewIoTrace};
+use crate::presence::{get_presence, NotifyUserByName};
+use crate::schema::{audit_logs, io_traces, test_runs};
+use crate::self_test::{io_matches, SelfTestConfig, SelfTestResult, SelfTestRun};
+use crate::test_vector::{TestReport, TestRun, TestScript, TestStepResult};
+use crate::ws_admin::{publish, AdminEvent};
 use crate::ws_board::{
-    ProgramBitstream, SendToBoard, WSBoard, WSBoardMessageB2S, WSBoardMessageS2B,
+    CloseBoard, ProgramBitstream, SendToBoard, WSBoard, WSBoardMessageB2S, WSBoardMessageS2B,
 };
-use crate::ws_user::RequestForBoardResult;
-use crate::ws_user::{BoardDisconnected, SendToUser, WSUser};
+use crate::ws_user::{BoardDisconnected, NotifyUser, SendToUser, WSUser, WSUserMessageS2U};
+use crate::ws_user::{RequestForBoardResult, TakeoverResult, WatchBoardResult};
+use crate::DbPool;
 use actix::prelude::*;
-use bimap::BiMap;
+use chrono::Utc;
+use diesel::prelude::*;
 use log::*;
 use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::Duration;
-use std::{
-    collections::VecDeque,
-    hash::{Hash, Hasher},
-};
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
 pub struct BoardInfo {
+    // identity of board, serial reported by board or its ip address
     pub remote: String,
+    // ip and port of the connection
+    pub address: String,
     pub software_version: String,
     pub hardware_version: String,
 }
@@ -26,28 +37,461 @@ struct BoardStat {
     info: BoardInfo,
 }
 
-#[derive(Eq)]
 struct UserStat {
     addr: Addr<WSUser>,
     user_name: String,
 }
 
-impl PartialEq for UserStat {
-    fn eq(&self, other: &Self) -> bool {
-        self.user_name == other.user_name
-    }
+struct BoardSession {
+    board: BoardStat,
+    owner: UserStat,
+    // teammates invited by owner, can drive the board as owner
+    invited: Vec<String>,
+    members: Vec<UserStat>,
+    // read-only users, receive the same messages as owner
+    observers: Vec<UserStat>,
+    // if set, only holder of control token can drive the board
+    controller: Option<String>,
+    // staff requesting or holding remote control, approved by owner
+    takeover_request: Option<UserStat>,
+    takeover: Option<UserStat>,
+    // last programmed job, for signal names of io trace
+    job_id: Option<i32>,
+    trace: Option<IOTraceRecording>,
+    // user actions are rejected while a test script is running
+    test_run: Option<TestRun>,
 }
 
-impl Hash for UserStat {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.user_name.hash(state);
+impl BoardSession {
+    fn new(board: BoardStat, owner: UserStat) -> Self {
+        Self {
+            board,
+            owner,
+            invited: vec![],
+            members: vec![],
+            observers: vec![],
+            controller: None,
+            takeover_request: None,
+            takeover: None,
+            job_id: None,
+            trace: None,
+            test_run: None,
+        }
     }
+
+    fn users(&self) -> impl Iterator<Item = &UserStat> {
+        std::iter::once(&self.owner)
+            .chain(self.members.iter())
+            .chain(self.observers.iter())
+            .chain(self.takeover.iter())
+    }
+
+    fn notify(&self, message: WSUserMessageS2U) {
+        for user in self.users() {
+            user.addr.do_send(NotifyUser(message.clone()));
+        }
+    }
+
+    fn can_drive(&self, user_name: &str) -> bool {
+        if let Some(takeover) = &self.takeover {
+            if takeover.user_name == user_name {
+                return true;
+            }
+        }
+        match &self.controller {
+            Some(controller) => controller == user_name,
+            None => true,
+        }
+    }
+}
+
+struct RunningSelfTest {
+    board: BoardStat,
+    run: SelfTestRun,
 }
 
+const SELF_TEST_PROGRAM_TIMEOUT: Duration = Duration::from_secs(60);
+const SELF_TEST_STEP_TIMEOUT: Duration = Duration::from_secs(2);
+const TEST_RUN_PROGRAM_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Default)]
 pub struct BoardManagerActor {
     idle_boards: VecDeque<BoardStat>,
-    connections: BiMap<UserStat, BoardStat>,
+    maintenance_boards: VecDeque<BoardStat>,
+    sessions: Vec<BoardSession>,
+    health: HashMap<String, BoardHealth>,
+    self_tests: HashMap<String, RunningSelfTest>,
+    db: Option<DbPool>,
+}
+
+impl BoardManagerActor {
+    // connected boards, idle or not
+    fn boards(&self) -> impl Iterator<Item = &BoardStat> {
+        self.idle_boards
+            .iter()
+            .chain(self.maintenance_boards.iter())
+            .chain(self.sessions.iter().map(|session| &session.board))
+            .chain(self.self_tests.values().map(|test| &test.board))
+            .filter(|board| board.addr.connected())
+    }
+
+    // put board back to idle pool, unless it is under maintenance
+    fn release_board(&mut self, board: BoardStat) {
+        let maintenance = self
+            .health
+            .get(&board.info.remote)
+            .map(|health| health.maintenance.is_some())
+            .unwrap_or(false);
+        if maintenance {
+            self.maintenance_boards.push_front(board);
+        } else {
+            self.idle_boards.push_front(board);
+        }
+    }
+
+    fn session_by_owner(&self, user_name: &str) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|session| session.owner.user_name == user_name)
+    }
+
+    // owner, teammates or staff taking over, matched by connection as the
+    // same user may have other connections in other sessions. A connection
+    // drives at most one session, see `is_driving`
+    fn session_by_driver(&self, user: &Addr<WSUser>) -> Option<usize> {
+        self.sessions.iter().position(|session| {
+            session.owner.addr == *user
+                || session.members.iter().any(|member| member.addr == *user)
+                || session
+                    .takeover
+                    .as_ref()
+                    .map(|takeover| takeover.addr == *user)
+                    .unwrap_or(false)
+        })
+    }
+
+    fn is_driving(&self, user: &Addr<WSUser>) -> bool {
+        self.session_by_driver(user).is_some()
+    }
+
+    fn audit(&self, actor: &str, action: &str, target: &str, board: &str) {
+        info!("audit: {} {} {} on board {}", actor, action, target, board);
+        if let Some(db) = &self.db {
+            let log = NewAuditLog {
+                actor: String::from(actor),
+                action: String::from(action),
+                target: String::from(target),
+                detail: Some(String::from(board)),
+            };
+            let res = db.get().map_err(failure::Error::from).and_then(|conn| {
+                diesel::insert_into(audit_logs::table)
+                    .values(&log)
+                    .execute(&conn)
+                    .map_err(failure::Error::from)
+            });
+            if let Err(err) = res {
+                warn!("failed to write audit log: {}", err);
+            }
+        }
+    }
+
+    fn save_trace(
+        &self,
+        owner: &UserStat,
+        board: &str,
+        job_id: Option<i32>,
+        trace: IOTraceRecording,
+    ) {
+        if let Some(db) = &self.db {
+            let trace = NewIoTrace {
+                user_name: owner.user_name.clone(),
+                board: String::from(board),
+                job_id,
+                started_at: trace.started_at,
+                events: serde_json::to_string(&trace.events).expect("to json"),
+            };
+            let res = db.get().map_err(failure::Error::from).and_then(|conn| {
+                diesel::insert_into(io_traces::table)
+                    .values(&trace)
+                    .returning(io_traces::dsl::id)
+                    .get_result::<i32>(&conn)
+                    .map_err(failure::Error::from)
+            });
+            match res {
+                Ok(id) => {
+                    info!("io trace {} of user {} saved", id, owner.user_name);
+                    owner
+                        .addr
+                        .do_send(NotifyUser(WSUserMessageS2U::IOTraceSaved(id)));
+                }
+                Err(err) => warn!("failed to save io trace: {}", err),
+            }
+        }
+    }
+
+    fn save_test_report(&self, id: i32, board: &str, report: &TestReport) {
+        if let Some(db) = &self.db {
+            let res = db.get().map_err(failure::Error::from).and_then(|conn| {
+                diesel::update(test_runs::dsl::test_runs.find(id))
+                    .set((
+                        test_runs::dsl::board.eq(board),
+                        test_runs::dsl::passed.eq(report.passed),
+                        test_runs::dsl::report.eq(serde_json::to_string(report).expect("to json")),
+                        test_runs::dsl::finished_at.eq(Utc::now()),
+                    ))
+                    .execute(&conn)
+                    .map_err(failure::Error::from)
+            });
+            if let Err(err) = res {
+                warn!("failed to save test report: {}", err);
+            }
+        }
+    }
+
+    fn session_by_test_run(&self, id: i32) -> Option<usize> {
+        self.sessions.iter().position(|session| {
+            session
+                .test_run
+                .as_ref()
+                .map(|run| run.id == id)
+                .unwrap_or(false)
+        })
+    }
+
+    fn finish_test_run(&mut self, index: usize, message: Option<String>) {
+        let session = &mut self.sessions[index];
+        if let Some(run) = session.test_run.take() {
+            let id = run.id;
+            let report = run.report(message);
+            info!(
+                "test run {} on board {} finished, passed {}",
+                id, session.board.info.remote, report.passed
+            );
+            session
+                .owner
+                .addr
+                .do_send(NotifyUser(WSUserMessageS2U::TestRunFinished {
+                    id,
+                    report: report.clone(),
+                }));
+            let board = session.board.info.remote.clone();
+            self.save_test_report(id, &board, &report);
+        }
+    }
+
+    fn test_run_step(&mut self, index: usize, step: usize, ctx: &mut Context<Self>) {
+        let session = &mut self.sessions[index];
+        let run = match &mut session.test_run {
+            Some(run) => run,
+            None => return,
+        };
+        if step >= run.script.steps.len() {
+            self.finish_test_run(index, None);
+            return;
+        }
+        run.step = Some(step);
+        let test_step = &run.script.steps[step];
+        if let Some(output) = &test_step.output {
+            session.board.addr.do_send(SendToBoard {
+                action: WSBoardMessageS2B::SetIOOutput(output.clone()),
+            });
+        }
+        if let Some(clock) = &test_step.clock {
+            let action = if clock.frequency == 0 {
+                WSBoardMessageS2B::DisableUserClock(String::new())
+            } else {
+                WSBoardMessageS2B::EnableUserClock(clock.clone())
+            };
+            session.board.addr.do_send(SendToBoard { action });
+        }
+        let id = run.id;
+        ctx.run_later(Duration::from_millis(test_step.delay), move |actor, ctx| {
+            let index = match actor.session_by_test_run(id) {
+                Some(index) => index,
+                None => return,
+            };
+            if let Some(run) = &mut actor.sessions[index].test_run {
+                if run.step != Some(step) {
+                    return;
+                }
+                let passed = match (&run.script.steps[step].expected, &run.last_io) {
+                    (Some(expected), Some(actual)) => io_matches(expected, actual),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+                run.results.push(TestStepResult {
+                    step,
+                    passed,
+                    actual: run.last_io.clone(),
+                });
+            }
+            actor.test_run_step(index, step + 1, ctx);
+        });
+    }
+
+    fn handle_test_run_report(
+        &mut self,
+        index: usize,
+        action: &WSBoardMessageB2S,
+        ctx: &mut Context<Self>,
+    ) {
+        let session = &mut self.sessions[index];
+        let run = match &mut session.test_run {
+            Some(run) => run,
+            None => return,
+        };
+        match (action, run.step) {
+            (WSBoardMessageB2S::ProgramBitstreamFinish(false), None) => {
+                self.finish_test_run(index, Some(String::from("program failed")));
+            }
+            (WSBoardMessageB2S::ProgramBitstreamFinish(true), None) => {
+                session.board.addr.do_send(SendToBoard {
+                    action: WSBoardMessageS2B::SetIODirection(run.script.direction.clone()),
+                });
+                session.board.addr.do_send(SendToBoard {
+                    action: WSBoardMessageS2B::SubscribeIOChange(String::new()),
+                });
+                self.test_run_step(index, 0, ctx);
+            }
+            (WSBoardMessageB2S::ReportIOChange(io), _) => {
+                run.record_io(io);
+            }
+            _ => {}
+        }
+    }
+
+    fn session_by_board(&self, board: &Addr<WSBoard>) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|session| session.board.addr == *board)
+    }
+
+    fn check_health(&mut self, remote: &str) {
+        let health = match self.health.get_mut(remote) {
+            Some(health) if health.maintenance.is_none() => health,
+            _ => return,
+        };
+        if let Some(reason) = health.check_fault() {
+            health.maintenance = Some(reason.clone());
+            warn!("board {} goes into maintenance: {}", remote, reason);
+            publish(AdminEvent::BoardMaintenance {
+                board: String::from(remote),
+                reason: Some(reason.clone()),
+            });
+            sentry::capture_message(
+                &format!("board {} goes into maintenance: {}", remote, reason),
+                sentry::Level::Warning,
+            );
+            if let Some(index) = self
+                .idle_boards
+                .iter()
+                .position(|board| board.info.remote == remote)
+            {
+                let board = self.idle_boards.remove(index).unwrap();
+                self.maintenance_boards.push_front(board);
+            }
+        }
+    }
+
+    fn finish_self_test(&mut self, remote: &str, passed: bool, message: String) {
+        if let Some(test) = self.self_tests.remove(remote) {
+            info!(
+                "board {} self test finished, passed {}: {}",
+                remote, passed, message
+            );
+            test.board.addr.do_send(SendToBoard {
+                action: WSBoardMessageS2B::UnsubscribeIOChange(String::new()),
+            });
+            if let Some(health) = self.health.get_mut(remote) {
+                if !passed {
+                    warn!("board {} goes into maintenance: self test failed", remote);
+                    sentry::capture_message(
+                        &format!("board {} failed self test: {}", remote, message),
+                        sentry::Level::Warning,
+                    );
+                    health.maintenance = Some(format!("self test failed: {}", message));
+                    publish(AdminEvent::BoardMaintenance {
+                        board: String::from(remote),
+                        reason: health.maintenance.clone(),
+                    });
+                }
+                health.self_test = Some(SelfTestResult {
+                    time: Utc::now(),
+                    passed: Some(passed),
+                    message,
+                });
+            }
+            self.release_board(test.board);
+        }
+    }
+
+    fn schedule_self_test_timeout(&self, remote: &str, timeout: Duration, ctx: &mut Context<Self>) {
+        if let Some(test) = self.self_tests.get(remote) {
+            let remote = String::from(remote);
+            let id = test.run.id.clone();
+            let step = test.run.step;
+            ctx.run_later(timeout, move |actor, _ctx| {
+                if let Some(test) = actor.self_tests.get(&remote) {
+                    if test.run.id == id && test.run.step == step {
+                        let message = match step {
+                            Some(step) => format!("timeout at vector {}", step),
+                            None => String::from("timeout when programming"),
+                        };
+                        actor.finish_self_test(&remote, false, message);
+                    }
+                }
+            });
+        }
+    }
+
+    fn self_test_step(&mut self, remote: &str, step: usize, ctx: &mut Context<Self>) {
+        if let Some(test) = self.self_tests.get_mut(remote) {
+            if step >= test.run.config.vectors.len() {
+                self.finish_self_test(remote, true, format!("{} vectors passed", step));
+            } else {
+                test.run.step = Some(step);
+                test.board.addr.do_send(SendToBoard {
+                    action: WSBoardMessageS2B::SetIOOutput(
+                        test.run.config.vectors[step].output.clone(),
+                    ),
+                });
+                self.schedule_self_test_timeout(remote, SELF_TEST_STEP_TIMEOUT, ctx);
+            }
+        }
+    }
+
+    fn handle_self_test_report(
+        &mut self,
+        remote: &str,
+        action: &WSBoardMessageB2S,
+        ctx: &mut Context<Self>,
+    ) {
+        let test = match self.self_tests.get(remote) {
+            Some(test) => test,
+            None => return,
+        };
+        match (action, test.run.step) {
+            (WSBoardMessageB2S::ProgramBitstreamFinish(false), None) => {
+                self.finish_self_test(remote, false, String::from("program failed"));
+            }
+            (WSBoardMessageB2S::ProgramBitstreamFinish(true), None) => {
+                test.board.addr.do_send(SendToBoard {
+                    action: WSBoardMessageS2B::SetIODirection(test.run.config.direction.clone()),
+                });
+                test.board.addr.do_send(SendToBoard {
+                    action: WSBoardMessageS2B::SubscribeIOChange(String::new()),
+                });
+                self.self_test_step(remote, 0, ctx);
+            }
+            (WSBoardMessageB2S::ReportIOChange(io), Some(step)) => {
+                // mismatch may be transient, wait for timeout
+                if io_matches(&test.run.config.vectors[step].expected, io) {
+                    self.self_test_step(remote, step + 1, ctx);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 impl actix::Supervised for BoardManagerActor {}
@@ -64,44 +508,219 @@ impl Actor for BoardManagerActor {
     fn started(&mut self, ctx: &mut Context<Self>) {
         // cleanup disconnected clients
         ctx.run_interval(Duration::from_secs(5), |actor, _ctx| {
+            for board in actor.idle_boards.iter().chain(&actor.maintenance_boards) {
+                if !board.addr.connected() {
+                    publish(AdminEvent::BoardLeft(board.info.clone()));
+                }
+            }
             actor.idle_boards.retain(|board| board.addr.connected());
-            for (user, board) in &actor.connections {
-                if !user.addr.connected() && board.addr.connected() {
-                    actor.idle_boards.push_front(board.clone());
-                } else if user.addr.connected() && !board.addr.connected() {
-                    user.addr.do_send(BoardDisconnected);
+            actor
+                .maintenance_boards
+                .retain(|board| board.addr.connected());
+            let mut released = vec![];
+            let mut ended = vec![];
+            let mut traces = vec![];
+            let mut test_runs = vec![];
+            for session in &mut actor.sessions {
+                let board = &session.board;
+                let owner = &session.owner;
+                if !owner.addr.connected() || !board.addr.connected() {
+                    publish(AdminEvent::BoardReleased {
+                        user_name: owner.user_name.clone(),
+                        board: board.info.remote.clone(),
+                    });
+                    for user in session.users() {
+                        if user.addr.connected() {
+                            user.addr.do_send(BoardDisconnected);
+                        }
+                    }
+                    // pending request is answered as denied
+                    if let Some(request) = &session.takeover_request {
+                        request.addr.do_send(TakeoverResult(None));
+                    }
+                    if let Some(run) = session.test_run.take() {
+                        test_runs.push((
+                            board.info.remote.clone(),
+                            run.id,
+                            run.report(Some(String::from("board disconnected"))),
+                        ));
+                    }
+                    if let Some(trace) = session.trace.take() {
+                        traces.push((
+                            UserStat {
+                                addr: owner.addr.clone(),
+                                user_name: owner.user_name.clone(),
+                            },
+                            board.info.remote.clone(),
+                            session.job_id,
+                            trace,
+                        ));
+                    }
+                    if board.addr.connected() {
+                        released.push(board.clone());
+                    } else {
+                        publish(AdminEvent::BoardLeft(board.info.clone()));
+                    }
+                } else {
+                    if let Some(takeover) = &session.takeover {
+                        if !takeover.addr.connected() {
+                            ended.push((
+                                takeover.user_name.clone(),
+                                owner.user_name.clone(),
+                                board.info.remote.clone(),
+                            ));
+                            owner
+                                .addr
+                                .do_send(NotifyUser(WSUserMessageS2U::TakeoverChanged(None)));
+                        }
+                    }
+                    if let Some(request) = &session.takeover_request {
+                        if !request.addr.connected() {
+                            session.takeover_request = None;
+                        }
+                    }
+                    session.takeover = session
+                        .takeover
+                        .take()
+                        .filter(|takeover| takeover.addr.connected());
+                    // notify about teammates gone
+                    for member in &session.members {
+                        if !member.addr.connected() {
+                            session.notify(WSUserMessageS2U::MemberLeft(member.user_name.clone()));
+                        }
+                    }
+                    session.members.retain(|member| member.addr.connected());
+                    if let Some(controller) = &session.controller {
+                        if *controller != owner.user_name
+                            && !session
+                                .members
+                                .iter()
+                                .any(|member| member.user_name == *controller)
+                        {
+                            // token holder left, return it to owner
+                            session.controller = Some(owner.user_name.clone());
+                            session.notify(WSUserMessageS2U::ControlChanged(
+                                session.controller.clone(),
+                            ));
+                        }
+                    }
+                    // notify owner about observers gone
+                    for observer in &session.observers {
+                        if !observer.addr.connected() {
+                            owner
+                                .addr
+                                .do_send(NotifyUser(WSUserMessageS2U::SpectatorLeft(
+                                    observer.user_name.clone(),
+                                )));
+                        }
+                    }
+                    session
+                        .observers
+                        .retain(|observer| observer.addr.connected());
                 }
             }
-            actor.connections.retain(|user, board| {
-                return user.addr.connected() && board.addr.connected();
+            actor.sessions.retain(|session| {
+                return session.owner.addr.connected() && session.board.addr.connected();
             });
+            for board in released {
+                actor.release_board(board);
+            }
+            for (user_name, target, board) in ended {
+                actor.audit(&user_name, "takeover_ended", &target, &board);
+            }
+            for (owner, board, job_id, trace) in traces {
+                actor.save_trace(&owner, &board, job_id, trace);
+            }
+            for (board, id, report) in test_runs {
+                actor.save_test_report(id, &board, &report);
+            }
+            let lost: Vec<String> = actor
+                .self_tests
+                .iter()
+                .filter(|(_remote, test)| !test.board.addr.connected())
+                .map(|(remote, _test)| remote.clone())
+                .collect();
+            for remote in lost {
+                actor.finish_self_test(&remote, false, String::from("board disconnected"));
+            }
+            let connected: HashSet<String> = actor
+                .boards()
+                .map(|board| board.info.remote.clone())
+                .collect();
+            for (remote, health) in actor.health.iter_mut() {
+                health.tick(connected.contains(remote));
+            }
+            actor.health.retain(|_remote, health| !health.expired());
         });
     }
 }
 
+/// Register an authenticated board, returns the identity assigned to it
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "String")]
 pub struct RegisterBoard {
     pub addr: Addr<WSBoard>,
     pub info: BoardInfo,
+    pub serial: Option<String>,
 }
 
 impl Handler<RegisterBoard> for BoardManagerActor {
-    type Result = ();
+    type Result = String;
 
-    fn handle(&mut self, board: RegisterBoard, _ctx: &mut Context<Self>) -> () {
-        info!("board registered {:?}", board.info,);
-        self.idle_boards.push_front(BoardStat {
+    fn handle(&mut self, board: RegisterBoard, _ctx: &mut Context<Self>) -> String {
+        let mut info = board.info;
+        if let Some(serial) = board.serial {
+            info.remote = serial;
+            // the same board reconnected before its old connection times out
+            let stale: Vec<BoardStat> = self
+                .boards()
+                .filter(|old| old.info.remote == info.remote && old.addr != board.addr)
+                .cloned()
+                .collect();
+            for old in stale {
+                warn!(
+                    "board {} reconnected from {}, closing old connection from {}",
+                    info.remote, info.address, old.info.address
+                );
+                old.addr.do_send(CloseBoard);
+                self.idle_boards.retain(|board| board.addr != old.addr);
+                self.maintenance_boards
+                    .retain(|board| board.addr != old.addr);
+                publish(AdminEvent::BoardLeft(old.info));
+            }
+        } else if self.boards().any(|old| old.info.remote == info.remote) {
+            // another board behind the same ip, tell them apart by port
+            warn!(
+                "board {} is already connected, identify {} by address",
+                info.remote, info.address
+            );
+            info.remote = info.address.clone();
+        }
+        info!("board registered {:?}", info);
+        publish(AdminEvent::BoardRegistered(info.clone()));
+        let remote = info.remote.clone();
+        if let Some(health) = self.health.get_mut(&remote) {
+            health.record_reconnect();
+        } else {
+            self.health.insert(remote.clone(), BoardHealth::new());
+        }
+        self.release_board(BoardStat {
             addr: board.addr,
-            info: board.info,
+            info,
         });
+        self.check_health(&remote);
+        remote
     }
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct BoardInfo2 {
     pub board: BoardInfo,
     pub connected_user: Option<String>,
+    pub members: Vec<String>,
+    pub observers: Vec<String>,
+    pub controller: Option<String>,
+    pub health: Option<BoardHealthInfo>,
 }
 
 #[derive(MessageResponse)]
@@ -116,16 +735,43 @@ impl Handler<GetBoardList> for BoardManagerActor {
 
     fn handle(&mut self, _req: GetBoardList, _ctx: &mut Context<Self>) -> BoardInfoList {
         let mut res = vec![];
-        for (user, board) in &self.connections {
+        let health = |board: &BoardStat| {
+            self.health
+                .get(&board.info.remote)
+                .map(|health| health.info())
+        };
+        for session in &self.sessions {
             res.push(BoardInfo2 {
-                board: board.info.clone(),
-                connected_user: Some(user.user_name.clone()),
+                board: session.board.info.clone(),
+                connected_user: Some(session.owner.user_name.clone()),
+                members: session
+                    .members
+                    .iter()
+                    .map(|member| member.user_name.clone())
+                    .collect(),
+                controller: session.controller.clone(),
+                observers: session
+                    .observers
+                    .iter()
+                    .map(|observer| observer.user_name.clone())
+                    .collect(),
+                health: health(&session.board),
             });
         }
-        for board in &self.idle_boards {
+        let testing = self.self_tests.values().map(|test| &test.board);
+        for board in self
+            .idle_boards
+            .iter()
+            .chain(&self.maintenance_boards)
+            .chain(testing)
+        {
             res.push(BoardInfo2 {
                 board: board.info.clone(),
                 connected_user: None,
+                members: vec![],
+                observers: vec![],
+                controller: None,
+                health: health(board),
             });
         }
         BoardInfoList(res)
@@ -145,23 +791,41 @@ impl Handler<RequestForBoard> for BoardManagerActor {
 
     fn handle(&mut self, req: RequestForBoard, _ctx: &mut Context<Self>) {
         let addr = req.user.clone();
+        let user_name = req.user_name.clone();
         let user_stat = UserStat {
             addr: req.user,
             user_name: req.user_name,
         };
-        if let Some(board) = self.connections.get_by_left(&user_stat).cloned() {
+        if let Some(index) = self.session_by_owner(&user_stat.user_name) {
             // this user has one connection already, remove old one
-            let old = self.connections.remove_by_right(&board);
-            self.idle_boards.push_front(board);
-            if let Some((old_user, old_board)) = old {
-                old_user.addr.do_send(BoardDisconnected);
-                info!(
-                    "kicking user {} old connection to board {}",
-                    old_user.user_name, old_board.info.remote
-                );
+            self.finish_test_run(index, Some(String::from("board released")));
+            let mut old = self.sessions.remove(index);
+            if let Some(trace) = old.trace.take() {
+                self.save_trace(&old.owner, &old.board.info.remote, old.job_id, trace);
+            }
+            for user in old.users() {
+                user.addr.do_send(BoardDisconnected);
             }
+            if let Some(request) = &old.takeover_request {
+                request.addr.do_send(TakeoverResult(None));
+            }
+            publish(AdminEvent::BoardReleased {
+                user_name: old.owner.user_name.clone(),
+                board: old.board.info.remote.clone(),
+            });
+            info!(
+                "kicking user {} old connection to board {}",
+                old.owner.user_name, old.board.info.remote
+            );
+            self.release_board(old.board);
         }
-        let res = if req.hint != "" {
+        let res = if self.is_driving(&addr) {
+            info!(
+                "user {} is in another board session, can't allocate",
+                user_stat.user_name
+            );
+            None
+        } else if req.hint != "" {
             // find board by hint
             info!("looking for idle boards with hint {}", req.hint);
 
@@ -175,7 +839,7 @@ impl Handler<RequestForBoard> for BoardManagerActor {
                         user_stat.user_name, board.info.remote, req.hint
                     );
                     res = Some(remote);
-                    self.connections.insert(user_stat, board);
+                    self.sessions.push(BoardSession::new(board, user_stat));
                     break;
                 }
             }
@@ -191,12 +855,18 @@ impl Handler<RequestForBoard> for BoardManagerActor {
                 user_stat.user_name, board.info.remote
             );
             let remote = board.info.remote.clone();
-            self.connections.insert(user_stat, board);
+            self.sessions.push(BoardSession::new(board, user_stat));
             Some(remote)
         } else {
             info!("no idle boards, can't allocate");
             None
         };
+        if let Some(remote) = &res {
+            publish(AdminEvent::BoardAllocated {
+                user_name: user_name,
+                board: remote.clone(),
+            });
+        }
         addr.do_send(RequestForBoardResult(res));
     }
 }
@@ -213,17 +883,42 @@ impl Handler<RouteToBoard> for BoardManagerActor {
     type Result = ();
 
     fn handle(&mut self, req: RouteToBoard, _ctx: &mut Context<Self>) {
-        let user_stat = UserStat {
-            addr: req.user,
-            user_name: req.user_name,
-        };
         // TODO: filter unneed actions
-        if let Some(board) = self.connections.get_by_left(&user_stat) {
+        if let Some(index) = self.session_by_driver(&req.user) {
+            let session = &mut self.sessions[index];
+            if session.test_run.is_some() {
+                info!(
+                    "user {} action rejected by running test on board {}",
+                    req.user_name, session.board.info.remote
+                );
+                return;
+            }
+            if !session.can_drive(&req.user_name) {
+                info!(
+                    "user {} does not hold control token of board {}",
+                    req.user_name, session.board.info.remote
+                );
+                return;
+            }
             info!(
                 "user {} send action {:?} to board {}",
-                user_stat.user_name, req.action, board.info.remote
+                req.user_name, req.action, session.board.info.remote
             );
-            board.addr.do_send(SendToBoard { action: req.action });
+            if let Some(trace) = &mut session.trace {
+                match &req.action {
+                    WSBoardMessageS2B::SetIOOutput(io) => {
+                        trace.record(IOTraceKind::SetIOOutput, io)
+                    }
+                    WSBoardMessageS2B::SetIODirection(io) => {
+                        trace.record(IOTraceKind::SetIODirection, io)
+                    }
+                    _ => {}
+                }
+            }
+            session
+                .board
+                .addr
+                .do_send(SendToBoard { action: req.action });
         }
     }
 }
@@ -233,6 +928,9 @@ impl Handler<RouteToBoard> for BoardManagerActor {
 pub struct ProgramBitstreamToBoard {
     pub user: Addr<WSUser>,
     pub user_name: String,
+    pub job_id: i32,
+    // jobs of others can only be programmed by staff taking over
+    pub submitter: String,
     pub data: bytes::Bytes,
 }
 
@@ -240,14 +938,27 @@ impl Handler<ProgramBitstreamToBoard> for BoardManagerActor {
     type Result = ();
 
     fn handle(&mut self, req: ProgramBitstreamToBoard, _ctx: &mut Context<Self>) {
-        let user_stat = UserStat {
-            addr: req.user,
-            user_name: req.user_name,
-        };
-        if let Some(board) = self.connections.get_by_left(&user_stat) {
-            board.addr.do_send(ProgramBitstream { data: req.data });
+        let index = self
+            .session_by_driver(&req.user)
+            .filter(|index| self.sessions[*index].can_drive(&req.user_name))
+            .filter(|index| self.sessions[*index].test_run.is_none())
+            .filter(|index| {
+                req.submitter == req.user_name
+                    || self.sessions[*index]
+                        .takeover
+                        .as_ref()
+                        .map(|takeover| takeover.addr == req.user)
+                        .unwrap_or(false)
+            });
+        if let Some(index) = index {
+            let session = &mut self.sessions[index];
+            session.job_id = Some(req.job_id);
+            session
+                .board
+                .addr
+                .do_send(ProgramBitstream { data: req.data });
         } else {
-            user_stat.addr.do_send(SendToUser {
+            req.user.do_send(SendToUser {
                 action: WSBoardMessageB2S::ProgramBitstreamFinish(false),
             });
         }
@@ -265,22 +976,120 @@ pub struct RouteToUser {
 impl Handler<RouteToUser> for BoardManagerActor {
     type Result = ();
 
-    fn handle(&mut self, req: RouteToUser, _ctx: &mut Context<Self>) {
+    fn handle(&mut self, req: RouteToUser, ctx: &mut Context<Self>) {
         let board = BoardStat {
             addr: req.addr,
             info: req.info,
         };
+        if let Some(health) = self.health.get_mut(&board.info.remote) {
+            match &req.action {
+                WSBoardMessageB2S::ProgramBitstreamFinish(success) => {
+                    health.record_program(*success);
+                }
+                WSBoardMessageB2S::ReportIOChange(_) => {
+                    health.record_io_update();
+                }
+                _ => {}
+            }
+        }
+        self.check_health(&board.info.remote);
+        if self.self_tests.contains_key(&board.info.remote) {
+            self.handle_self_test_report(&board.info.remote, &req.action, ctx);
+            return;
+        }
         // TODO: filter unneed actions
-        if let Some(user) = self.connections.get_by_right(&board) {
+        if let Some(index) = self.session_by_board(&board.addr) {
+            let session = &mut self.sessions[index];
+            if let (Some(trace), WSBoardMessageB2S::ReportIOChange(io)) =
+                (&mut session.trace, &req.action)
+            {
+                trace.record(IOTraceKind::ReportIOChange, io);
+            }
             info!(
                 "board {} send action {:?} to user {}",
-                board.info.remote, req.action, user.user_name,
+                board.info.remote, req.action, session.owner.user_name,
             );
-            user.addr.do_send(SendToUser { action: req.action });
+            for user in session.users() {
+                user.addr.do_send(SendToUser {
+                    action: req.action.clone(),
+                });
+            }
+            self.handle_test_run_report(index, &req.action, ctx);
         }
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReportHeartbeat {
+    pub remote: String,
+    pub rtt: Duration,
+}
+
+impl Handler<ReportHeartbeat> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: ReportHeartbeat, _ctx: &mut Context<Self>) {
+        if let Some(health) = self.health.get_mut(&req.remote) {
+            health.record_rtt(req.rtt);
+        }
+        self.check_health(&req.remote);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct SetBoardMaintenance {
+    pub remote: String,
+    pub maintenance: bool,
+}
+
+impl Handler<SetBoardMaintenance> for BoardManagerActor {
+    type Result = bool;
+
+    fn handle(&mut self, req: SetBoardMaintenance, _ctx: &mut Context<Self>) -> bool {
+        let health = match self.health.get_mut(&req.remote) {
+            Some(health) => health,
+            None => return false,
+        };
+        if req.maintenance {
+            health.maintenance = Some(String::from("set by admin"));
+            if let Some(index) = self
+                .idle_boards
+                .iter()
+                .position(|board| board.info.remote == req.remote)
+            {
+                let board = self.idle_boards.remove(index).unwrap();
+                self.maintenance_boards.push_front(board);
+            }
+        } else {
+            health.maintenance = None;
+            health.reset_errors();
+            if let Some(index) = self
+                .maintenance_boards
+                .iter()
+                .position(|board| board.info.remote == req.remote)
+            {
+                let board = self.maintenance_boards.remove(index).unwrap();
+                self.idle_boards.push_front(board);
+            }
+        }
+        info!(
+            "board {} maintenance set to {}",
+            req.remote, req.maintenance
+        );
+        publish(AdminEvent::BoardMaintenance {
+            board: req.remote.clone(),
+            reason: if req.maintenance {
+                Some(String::from("set by admin"))
+            } else {
+                None
+            },
+        });
+        true
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "bool")]
 pub struct SendToBoardByRemote {
@@ -292,19 +1101,510 @@ impl Handler<SendToBoardByRemote> for BoardManagerActor {
     type Result = bool;
 
     fn handle(&mut self, req: SendToBoardByRemote, _ctx: &mut Context<Self>) -> bool {
-        for (_user, board) in &self.connections {
+        // identities of connected boards are unique
+        for board in self.boards() {
             if board.info.remote == req.remote {
                 board.addr.do_send(SendToBoard { action: req.action });
                 return true;
             }
         }
-        for board in &self.idle_boards {
-            if board.info.remote == req.remote {
-                board.addr.do_send(SendToBoard { action: req.action });
-                return true;
+        return false;
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct StartSelfTest {
+    pub remote: String,
+    pub config: SelfTestConfig,
+    pub bitstream: bytes::Bytes,
+}
+
+impl Handler<StartSelfTest> for BoardManagerActor {
+    type Result = bool;
+
+    fn handle(&mut self, req: StartSelfTest, ctx: &mut Context<Self>) -> bool {
+        // only idle boards or boards under maintenance can be tested
+        let board = if let Some(index) = self
+            .idle_boards
+            .iter()
+            .position(|board| board.info.remote == req.remote)
+        {
+            self.idle_boards.remove(index).unwrap()
+        } else if let Some(index) = self
+            .maintenance_boards
+            .iter()
+            .position(|board| board.info.remote == req.remote)
+        {
+            self.maintenance_boards.remove(index).unwrap()
+        } else {
+            info!("board {} is not idle, can't self test", req.remote);
+            return false;
+        };
+        info!("board {} self test started", req.remote);
+        if let Some(health) = self.health.get_mut(&req.remote) {
+            health.self_test = Some(SelfTestResult {
+                time: Utc::now(),
+                passed: None,
+                message: String::from("running"),
+            });
+        }
+        board.addr.do_send(ProgramBitstream {
+            data: req.bitstream,
+        });
+        self.self_tests.insert(
+            req.remote.clone(),
+            RunningSelfTest {
+                board,
+                run: SelfTestRun {
+                    id: generate_uuid(),
+                    config: req.config,
+                    step: None,
+                },
+            },
+        );
+        self.schedule_self_test_timeout(&req.remote, SELF_TEST_PROGRAM_TIMEOUT, ctx);
+        true
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchBoard {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+    // owner user name or board remote
+    pub target: String,
+}
+
+impl Handler<WatchBoard> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: WatchBoard, _ctx: &mut Context<Self>) {
+        let res = if let Some(session) = self.sessions.iter_mut().find(|session| {
+            session.owner.user_name == req.target || session.board.info.remote == req.target
+        }) {
+            info!(
+                "user {} watches board {} of user {}",
+                req.user_name, session.board.info.remote, session.owner.user_name
+            );
+            session
+                .owner
+                .addr
+                .do_send(NotifyUser(WSUserMessageS2U::SpectatorJoined(
+                    req.user_name.clone(),
+                )));
+            session.observers.push(UserStat {
+                addr: req.user.clone(),
+                user_name: req.user_name,
+            });
+            Some(session.board.info.remote.clone())
+        } else {
+            info!("no board session matching {}, can't watch", req.target);
+            None
+        };
+        req.user.do_send(WatchBoardResult(res));
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnwatchBoard {
+    pub user: Addr<WSUser>,
+}
+
+impl Handler<UnwatchBoard> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: UnwatchBoard, _ctx: &mut Context<Self>) {
+        for session in &mut self.sessions {
+            if let Some(index) = session
+                .observers
+                .iter()
+                .position(|observer| observer.addr == req.user)
+            {
+                let observer = session.observers.remove(index);
+                session
+                    .owner
+                    .addr
+                    .do_send(NotifyUser(WSUserMessageS2U::SpectatorLeft(
+                        observer.user_name,
+                    )));
             }
         }
-        return false;
+        req.user.do_send(WatchBoardResult(None));
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct InviteMember {
+    pub user_name: String,
+    pub member: String,
+}
+
+impl Handler<InviteMember> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: InviteMember, _ctx: &mut Context<Self>) {
+        if let Some(index) = self.session_by_owner(&req.user_name) {
+            let session = &mut self.sessions[index];
+            if !session.invited.contains(&req.member) {
+                session.invited.push(req.member.clone());
+            }
+            info!(
+                "user {} invites {} to board {}",
+                req.user_name, req.member, session.board.info.remote
+            );
+            get_presence().do_send(NotifyUserByName {
+                user_name: req.member,
+                message: WSUserMessageS2U::SessionInvitation(req.user_name),
+            });
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinSession {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+    pub owner: String,
+}
+
+impl Handler<JoinSession> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: JoinSession, _ctx: &mut Context<Self>) {
+        let mut res = None;
+        if self.is_driving(&req.user) {
+            info!(
+                "user {} is in another board session, can't join",
+                req.user_name
+            );
+        } else if let Some(index) = self.session_by_owner(&req.owner) {
+            let session = &mut self.sessions[index];
+            if session.invited.contains(&req.user_name) {
+                info!(
+                    "user {} joins board {} of user {}",
+                    req.user_name, session.board.info.remote, req.owner
+                );
+                session.notify(WSUserMessageS2U::MemberJoined(req.user_name.clone()));
+                session.members.push(UserStat {
+                    addr: req.user.clone(),
+                    user_name: req.user_name,
+                });
+                res = Some(session.board.info.remote.clone());
+            }
+        }
+        req.user.do_send(RequestForBoardResult(res));
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveSession {
+    pub user: Addr<WSUser>,
+}
+
+impl Handler<LeaveSession> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: LeaveSession, _ctx: &mut Context<Self>) {
+        for session in &mut self.sessions {
+            if let Some(index) = session
+                .members
+                .iter()
+                .position(|member| member.addr == req.user)
+            {
+                let member = session.members.remove(index);
+                req.user.do_send(BoardDisconnected);
+                session.notify(WSUserMessageS2U::MemberLeft(member.user_name.clone()));
+                if session.controller.as_ref() == Some(&member.user_name) {
+                    session.controller = Some(session.owner.user_name.clone());
+                    session.notify(WSUserMessageS2U::ControlChanged(session.controller.clone()));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetControlToken {
+    pub user_name: String,
+    pub enabled: bool,
+}
+
+impl Handler<SetControlToken> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: SetControlToken, _ctx: &mut Context<Self>) {
+        if let Some(index) = self.session_by_owner(&req.user_name) {
+            let session = &mut self.sessions[index];
+            session.controller = if req.enabled {
+                Some(req.user_name)
+            } else {
+                None
+            };
+            session.notify(WSUserMessageS2U::ControlChanged(session.controller.clone()));
+        }
+    }
+}
+
+/// Start or stop recording io trace, trace is saved when stopped or session ends
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetIORecording {
+    pub user_name: String,
+    pub enabled: bool,
+}
+
+impl Handler<SetIORecording> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: SetIORecording, _ctx: &mut Context<Self>) {
+        if let Some(index) = self.session_by_owner(&req.user_name) {
+            let session = &mut self.sessions[index];
+            if req.enabled {
+                if session.trace.is_none() {
+                    session.trace = Some(IOTraceRecording::new());
+                }
+                session.notify(WSUserMessageS2U::IORecording(true));
+            } else {
+                session.notify(WSUserMessageS2U::IORecording(false));
+                if let Some(trace) = session.trace.take() {
+                    let session = &self.sessions[index];
+                    self.save_trace(
+                        &session.owner,
+                        &session.board.info.remote,
+                        session.job_id,
+                        trace,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PassControl {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+    pub to: String,
+}
+
+impl Handler<PassControl> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: PassControl, _ctx: &mut Context<Self>) {
+        if let Some(index) = self.session_by_driver(&req.user) {
+            let session = &mut self.sessions[index];
+            // owner can always take token back
+            let allowed = session.owner.user_name == req.user_name
+                || session.controller.as_ref() == Some(&req.user_name);
+            let target_exists = session.owner.user_name == req.to
+                || session
+                    .members
+                    .iter()
+                    .any(|member| member.user_name == req.to);
+            if session.controller.is_some() && allowed && target_exists {
+                info!(
+                    "user {} passes control of board {} to {}",
+                    req.user_name, session.board.info.remote, req.to
+                );
+                session.controller = Some(req.to);
+                session.notify(WSUserMessageS2U::ControlChanged(session.controller.clone()));
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RequestTakeover {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+    // owner user name or board remote
+    pub target: String,
+}
+
+impl Handler<RequestTakeover> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: RequestTakeover, _ctx: &mut Context<Self>) {
+        if self.is_driving(&req.user) {
+            info!(
+                "user {} is in another board session, can't take over",
+                req.user_name
+            );
+            req.user.do_send(TakeoverResult(None));
+            return;
+        }
+        let session = self.sessions.iter_mut().find(|session| {
+            session.owner.user_name == req.target || session.board.info.remote == req.target
+        });
+        let session = match session {
+            Some(session) if session.takeover.is_none() && session.takeover_request.is_none() => {
+                session
+            }
+            _ => {
+                info!("user {} can't take over {}", req.user_name, req.target);
+                req.user.do_send(TakeoverResult(None));
+                return;
+            }
+        };
+        session
+            .owner
+            .addr
+            .do_send(NotifyUser(WSUserMessageS2U::TakeoverRequested(
+                req.user_name.clone(),
+            )));
+        session.takeover_request = Some(UserStat {
+            addr: req.user,
+            user_name: req.user_name.clone(),
+        });
+        let target = session.owner.user_name.clone();
+        let board = session.board.info.remote.clone();
+        self.audit(&req.user_name, "takeover_requested", &target, &board);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ApproveTakeover {
+    pub user_name: String,
+    pub approve: bool,
+}
+
+impl Handler<ApproveTakeover> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: ApproveTakeover, _ctx: &mut Context<Self>) {
+        if let Some(index) = self.session_by_owner(&req.user_name) {
+            let session = &self.sessions[index];
+            // requester may have joined another session meanwhile
+            let busy = session
+                .takeover_request
+                .as_ref()
+                .map(|request| self.is_driving(&request.addr))
+                .unwrap_or(false);
+            let session = &mut self.sessions[index];
+            if let Some(request) = session.takeover_request.take() {
+                let board = session.board.info.remote.clone();
+                let action = if req.approve && !busy {
+                    request.addr.do_send(TakeoverResult(Some(board.clone())));
+                    let user_name = request.user_name.clone();
+                    session.takeover = Some(request);
+                    session.notify(WSUserMessageS2U::TakeoverChanged(Some(user_name.clone())));
+                    (user_name, "takeover_approved")
+                } else {
+                    request.addr.do_send(TakeoverResult(None));
+                    (request.user_name, "takeover_denied")
+                };
+                self.audit(&action.0, action.1, &req.user_name, &board);
+            }
+        }
+    }
+}
+
+/// Either owner revokes or staff gives up remote control
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EndTakeover {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+}
+
+impl Handler<EndTakeover> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: EndTakeover, _ctx: &mut Context<Self>) {
+        let index = self.sessions.iter().position(|session| {
+            session.owner.user_name == req.user_name
+                || session
+                    .takeover
+                    .as_ref()
+                    .map(|takeover| takeover.addr == req.user)
+                    .unwrap_or(false)
+        });
+        if let Some(index) = index {
+            let session = &mut self.sessions[index];
+            if let Some(takeover) = session.takeover.take() {
+                takeover.addr.do_send(TakeoverResult(None));
+                session.notify(WSUserMessageS2U::TakeoverChanged(None));
+                let action = if session.owner.user_name == req.user_name {
+                    "takeover_revoked"
+                } else {
+                    "takeover_ended"
+                };
+                let target = session.owner.user_name.clone();
+                let board = session.board.info.remote.clone();
+                self.audit(&takeover.user_name, action, &target, &board);
+            }
+        }
+    }
+}
+
+/// Program job and run test script on board of the user, false if user has
+/// no board or a test is running
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct StartTestRun {
+    pub user_name: String,
+    pub id: i32,
+    pub job_id: i32,
+    pub script: TestScript,
+    pub bitstream: bytes::Bytes,
+}
+
+impl Handler<StartTestRun> for BoardManagerActor {
+    type Result = bool;
+
+    fn handle(&mut self, req: StartTestRun, ctx: &mut Context<Self>) -> bool {
+        let index = match self.session_by_owner(&req.user_name) {
+            Some(index) if self.sessions[index].test_run.is_none() => index,
+            _ => return false,
+        };
+        let session = &mut self.sessions[index];
+        info!(
+            "start test run {} of job {} on board {}",
+            req.id, req.job_id, session.board.info.remote
+        );
+        session.job_id = Some(req.job_id);
+        session.test_run = Some(TestRun::new(req.id, req.script));
+        session.board.addr.do_send(ProgramBitstream {
+            data: req.bitstream,
+        });
+        let id = req.id;
+        ctx.run_later(TEST_RUN_PROGRAM_TIMEOUT, move |actor, _ctx| {
+            if let Some(index) = actor.session_by_test_run(id) {
+                let programming = actor.sessions[index]
+                    .test_run
+                    .as_ref()
+                    .map(|run| run.step.is_none())
+                    .unwrap_or(false);
+                if programming {
+                    actor.finish_test_run(index, Some(String::from("timeout when programming")));
+                }
+            }
+        });
+        true
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetDb {
+    pub db: DbPool,
+}
+
+impl Handler<SetDb> for BoardManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: SetDb, _ctx: &mut Context<Self>) {
+        self.db = Some(req.db);
     }
 }
 
@@ -0,0 +1,106 @@
+use crate::common::read_tar_entries;
+use crate::job_status::JobStatus;
+use crate::models::Job;
+use crate::schema::jobs;
+use crate::DbConnection;
+use diesel::prelude::*;
+use ring::digest;
+
+// metadata that changes the bitstream, others like directions are only used
+// by frontend
+const BUILD_METADATA_KEYS: [&str; 1] = ["lang"];
+
+/// Hash files in the source archive, so archives packed at different times
+/// still match. Computed by build worker which downloads the source anyway
+pub fn content_hash(source: &[u8]) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    // raw bytes, sources may not be in utf-8
+    let mut files = read_tar_entries(source);
+    if files.is_empty() {
+        // not a tar archive, take it as is
+        ctx.update(b"raw\0");
+        ctx.update(source);
+    } else {
+        files.sort();
+        for (name, content) in files {
+            ctx.update(name.as_bytes());
+            ctx.update(b"\0");
+            ctx.update(&(content.len() as u64).to_le_bytes());
+            ctx.update(content);
+        }
+    }
+    hex::encode(ctx.finish())
+}
+
+/// Key of build result, content hash of source with build related metadata
+pub fn cache_key(content_hash: &str, metadata: &str) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(content_hash.as_bytes());
+    ctx.update(b"\0");
+    let metadata = serde_json::from_str::<serde_json::Value>(metadata).unwrap_or_default();
+    for key in BUILD_METADATA_KEYS.iter() {
+        let value = metadata.get(key).cloned().unwrap_or_default();
+        ctx.update(key.as_bytes());
+        ctx.update(b"\0");
+        ctx.update(value.to_string().as_bytes());
+        ctx.update(b"\0");
+    }
+    hex::encode(ctx.finish())
+}
+
+/// Latest successful build of the same source whose result can be reused
+pub fn find_cached_build(conn: &DbConnection, hash: &str) -> QueryResult<Option<Job>> {
+    jobs::dsl::jobs
+        .filter(jobs::dsl::source_hash.eq(hash))
+        .filter(jobs::dsl::status.eq(JobStatus::Success))
+        .filter(jobs::dsl::destination.is_not_null())
+        .order(jobs::dsl::id.desc())
+        .first::<Job>(conn)
+        .optional()
+}
+
+mod test {
+    #[test]
+    fn hash_files_and_lang() {
+        use super::*;
+        // tar header with name, mode, mtime and size in octal
+        let tar = |name: &str, mtime: &str, content: &str| {
+            let mut header = vec![0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            let size = format!("{:011o}", content.len());
+            header[124..135].copy_from_slice(size.as_bytes());
+            header[136..136 + mtime.len()].copy_from_slice(mtime.as_bytes());
+            let mut data = header;
+            data.extend_from_slice(content.as_bytes());
+            data.resize(512 + (content.len() + 511) / 512 * 512, 0);
+            data.extend_from_slice(&[0u8; 1024]);
+            data
+        };
+        let verilog = r#"{"lang":"verilog","directions":{"1":"input"}}"#;
+        let source_hash =
+            |source: &[u8], metadata: &str| cache_key(&content_hash(source), metadata);
+        let a = source_hash(&tar("mod_top.v", "13740000000", "module"), verilog);
+        let b = source_hash(&tar("mod_top.v", "13740000001", "module"), verilog);
+        assert_eq!(a, b);
+        // directions do not matter
+        let c = source_hash(
+            &tar("mod_top.v", "13740000000", "module"),
+            r#"{"lang":"verilog","directions":{}}"#,
+        );
+        assert_eq!(a, c);
+        let d = source_hash(
+            &tar("mod_top.v", "13740000000", "module"),
+            r#"{"lang":"vhdl"}"#,
+        );
+        assert_ne!(a, d);
+        let e = source_hash(&tar("mod_top.v", "13740000000", "module "), verilog);
+        assert_ne!(a, e);
+        // differ only in bytes that are not utf-8, e.g. gbk literals
+        let gbk = |byte: u8| {
+            let mut data = tar("mod_top.v", "13740000000", "// ??");
+            data[515] = byte;
+            source_hash(&data, verilog)
+        };
+        assert_ne!(gbk(0xb2), gbk(0xb3));
+    }
+}
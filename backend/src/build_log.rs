@@ -0,0 +1,117 @@
+use crate::models::{Job, JobLog};
+use crate::schema::job_logs;
+use crate::ws_user::{NotifyUser, WSUser, WSUserMessageS2U};
+use crate::DbConnection;
+use actix::prelude::*;
+use diesel::prelude::*;
+use log::*;
+use std::collections::HashMap;
+
+pub const STREAMS: [&str; 2] = ["stdout", "stderr"];
+
+/// Log of the current attempt of a job, by stream
+pub fn job_log(conn: &DbConnection, job: &Job) -> QueryResult<HashMap<String, String>> {
+    let mut res: HashMap<String, String> = STREAMS
+        .iter()
+        .map(|stream| (String::from(*stream), String::new()))
+        .collect();
+    if let Some(task_id) = &job.task_id {
+        let chunks = job_logs::dsl::job_logs
+            .filter(job_logs::dsl::job_id.eq(job.id))
+            .filter(job_logs::dsl::task_id.eq(task_id))
+            .order(job_logs::dsl::id)
+            .load::<JobLog>(conn)?;
+        for chunk in chunks {
+            res.entry(chunk.stream).or_default().push_str(&chunk.data);
+        }
+    }
+    Ok(res)
+}
+
+/// Relay build log chunks to users watching the job
+#[derive(Default)]
+pub struct BuildLogActor {
+    subscribers: HashMap<i32, Vec<Addr<WSUser>>>,
+}
+
+impl actix::Supervised for BuildLogActor {}
+
+impl SystemService for BuildLogActor {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("build log service is up");
+    }
+}
+
+impl Actor for BuildLogActor {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribeJobLog {
+    pub job_id: i32,
+    pub user: Addr<WSUser>,
+    pub subscribe: bool,
+}
+
+impl Handler<SubscribeJobLog> for BuildLogActor {
+    type Result = ();
+
+    fn handle(&mut self, req: SubscribeJobLog, _ctx: &mut Context<Self>) {
+        let subscribers = self.subscribers.entry(req.job_id).or_default();
+        subscribers.retain(|addr| addr.connected() && *addr != req.user);
+        if req.subscribe {
+            subscribers.push(req.user);
+        }
+        if subscribers.is_empty() {
+            self.subscribers.remove(&req.job_id);
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PublishJobLog {
+    pub job_id: i32,
+    pub stream: String,
+    pub data: String,
+}
+
+impl Handler<PublishJobLog> for BuildLogActor {
+    type Result = ();
+
+    fn handle(&mut self, req: PublishJobLog, _ctx: &mut Context<Self>) {
+        if let Some(subscribers) = self.subscribers.get_mut(&req.job_id) {
+            subscribers.retain(|addr| addr.connected());
+            for addr in subscribers.iter() {
+                addr.do_send(NotifyUser(WSUserMessageS2U::JobLog {
+                    job_id: req.job_id,
+                    stream: req.stream.clone(),
+                    data: req.data.clone(),
+                }));
+            }
+            if subscribers.is_empty() {
+                self.subscribers.remove(&req.job_id);
+            }
+        }
+    }
+}
+
+/// Job is finished, no more chunks would come
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseJobLog {
+    pub job_id: i32,
+}
+
+impl Handler<CloseJobLog> for BuildLogActor {
+    type Result = ();
+
+    fn handle(&mut self, req: CloseJobLog, _ctx: &mut Context<Self>) {
+        self.subscribers.remove(&req.job_id);
+    }
+}
+
+pub fn get_build_log() -> Addr<BuildLogActor> {
+    BuildLogActor::from_registry()
+}
@@ -0,0 +1,311 @@
+use crate::job_status::JobStatus;
+use crate::models::{Assignment, Job, User};
+use crate::schema::{build_workers, configs, jobs};
+use crate::task_manager::{redis_transport, SubmitBuildTask};
+use crate::DbConnection;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Stored in configs under key "build_queue"
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BuildQueueConfig {
+    // queued or running jobs allowed per student, 0 for unlimited
+    pub max_pending_per_user: i64,
+    // priority of jobs by role of submitter, higher goes first
+    pub role_priority: HashMap<String, i32>,
+    // priority of jobs built for an assignment
+    pub assignment_priority: i32,
+}
+
+impl Default for BuildQueueConfig {
+    fn default() -> Self {
+        let mut role_priority = HashMap::new();
+        role_priority.insert(String::from("admin"), 10);
+        role_priority.insert(String::from("ta"), 10);
+        Self {
+            max_pending_per_user: 3,
+            role_priority,
+            assignment_priority: 5,
+        }
+    }
+}
+
+impl BuildQueueConfig {
+    pub fn priority(&self, user: &User, assignment: Option<&Assignment>) -> i32 {
+        let role = self.role_priority.get(&user.role).cloned().unwrap_or(0);
+        let assignment = assignment.map(|_| self.assignment_priority).unwrap_or(0);
+        role.max(assignment)
+    }
+}
+
+pub fn load_build_queue_config(conn: &DbConnection) -> QueryResult<BuildQueueConfig> {
+    let value = configs::dsl::configs
+        .select(configs::dsl::value)
+        .filter(configs::dsl::key.eq("build_queue"))
+        .first::<Option<String>>(conn)
+        .optional()?;
+    if let Some(Some(value)) = value {
+        if let Ok(config) = serde_json::from_str(&value) {
+            return Ok(config);
+        }
+    }
+    Ok(BuildQueueConfig::default())
+}
+
+pub fn save_build_queue_config(
+    conn: &DbConnection,
+    config: &BuildQueueConfig,
+) -> QueryResult<usize> {
+    let value = serde_json::to_string(config).expect("to json");
+    let kv = (
+        configs::dsl::key.eq("build_queue"),
+        configs::dsl::value.eq(&value),
+    );
+    diesel::insert_into(configs::table)
+        .values(kv)
+        .on_conflict(configs::dsl::key)
+        .do_update()
+        .set(kv)
+        .execute(conn)
+}
+
+/// Order queued jobs of (id, submitter, priority) by priority, then take
+/// turns between users, counting jobs already running
+pub fn fair_order(queued: &[(i32, String, i32)], running: &[String]) -> Vec<i32> {
+    let mut turns: HashMap<&str, usize> = HashMap::new();
+    for user_name in running {
+        *turns.entry(user_name.as_str()).or_insert(0) += 1;
+    }
+    let mut queued: Vec<&(i32, String, i32)> = queued.iter().collect();
+    queued.sort_by_key(|(id, _submitter, _priority)| *id);
+    let mut ranked = vec![];
+    for (id, submitter, priority) in queued {
+        let turn = turns.entry(submitter.as_str()).or_insert(0);
+        ranked.push((-priority, *turn, *id));
+        *turn += 1;
+    }
+    ranked.sort();
+    ranked
+        .into_iter()
+        .map(|(_priority, _turn, id)| id)
+        .collect()
+}
+
+// recent builds used for estimation
+const RECENT_BUILDS: i64 = 20;
+
+/// Current queue, for estimating when queued jobs start
+pub struct QueueSnapshot {
+    // queued jobs in the order they are taken
+    pub order: Vec<i32>,
+    pub running: usize,
+    pub workers: usize,
+    pub build_duration: Duration,
+}
+
+/// Start and finish time of a job with some jobs ahead, including running ones
+pub fn estimate(
+    ahead: usize,
+    workers: usize,
+    build_duration: Duration,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let rounds = (ahead / workers.max(1)) as i32;
+    let start = Utc::now() + build_duration * rounds;
+    (start, start + build_duration)
+}
+
+impl QueueSnapshot {
+    /// Position in queue counting from 1, estimated start and finish time
+    pub fn job_estimate(
+        &self,
+        job: &Job,
+    ) -> (Option<usize>, Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        match job.status {
+            JobStatus::Queued => match self.order.iter().position(|id| *id == job.id) {
+                Some(index) => {
+                    let (start, finish) =
+                        estimate(self.running + index, self.workers, self.build_duration);
+                    (Some(index + 1), Some(start), Some(finish))
+                }
+                None => (None, None, None),
+            },
+            // assume half way through
+            JobStatus::Running => (None, None, Some(Utc::now() + self.build_duration / 2)),
+            _ => (None, None, None),
+        }
+    }
+}
+
+lazy_static! {
+    // redis queue as last read by task manager: task ids waiting in the order
+    // workers take them, and the number of tasks taken by workers
+    static ref REDIS_QUEUE: RwLock<Option<(Vec<String>, usize)>> = RwLock::new(None);
+}
+
+/// Called by task manager with the waiting queue as stored in redis whenever
+/// it reads the queue, None when redis is unreachable
+pub fn update_redis_queue(queue: Option<(&[String], usize)>) {
+    let queue = queue.map(|(waiting, working)| {
+        // workers pop from the right
+        let waiting = waiting
+            .iter()
+            .rev()
+            .filter_map(|task| serde_json::from_str::<SubmitBuildTask>(task).ok())
+            .map(|task| task.id)
+            .collect();
+        (waiting, working)
+    });
+    *REDIS_QUEUE.write().unwrap() = queue;
+}
+
+/// Jobs pushed to redis go first, the rest are pushed later in fair order
+fn redis_order(
+    queued: &[(i32, String, i32, Option<String>)],
+    waiting: &[String],
+    running: &[String],
+) -> Vec<i32> {
+    let task_of = |task_id: &String| {
+        queued
+            .iter()
+            .find(|(_id, _submitter, _priority, task)| task.as_ref() == Some(task_id))
+            .map(|(id, _submitter, _priority, _task)| *id)
+    };
+    let mut order: Vec<i32> = waiting.iter().filter_map(task_of).collect();
+    let rest: Vec<(i32, String, i32)> = queued
+        .iter()
+        .filter(|(id, _submitter, _priority, _task)| !order.contains(id))
+        .map(|(id, submitter, priority, _task)| (*id, submitter.clone(), *priority))
+        .collect();
+    order.extend(fair_order(&rest, running));
+    order
+}
+
+pub fn queue_snapshot(conn: &DbConnection) -> QueryResult<QueueSnapshot> {
+    let queued = jobs::dsl::jobs
+        .select((
+            jobs::dsl::id,
+            jobs::dsl::submitter,
+            jobs::dsl::priority,
+            jobs::dsl::task_id,
+        ))
+        .filter(jobs::dsl::status.eq(JobStatus::Queued))
+        .load::<(i32, String, i32, Option<String>)>(conn)?;
+    let running = jobs::dsl::jobs
+        .select(jobs::dsl::submitter)
+        .filter(jobs::dsl::status.eq(JobStatus::Running))
+        .load::<String>(conn)?;
+    let recent = jobs::dsl::jobs
+        .select((jobs::dsl::created_at, jobs::dsl::finished_at))
+        .filter(jobs::dsl::status.eq_any(vec![JobStatus::Success, JobStatus::Failed]))
+        .filter(jobs::dsl::finished_at.is_not_null())
+        // reused results take no time
+        .filter(jobs::dsl::cache_hit.eq(false))
+        .order(jobs::dsl::id.desc())
+        .limit(RECENT_BUILDS)
+        .load::<(DateTime<Utc>, Option<DateTime<Utc>>)>(conn)?;
+    let durations: Vec<Duration> = recent
+        .into_iter()
+        .filter_map(|(created_at, finished_at)| {
+            finished_at.map(|finished_at| finished_at - created_at)
+        })
+        .collect();
+    let build_duration = if durations.is_empty() {
+        Duration::minutes(3)
+    } else {
+        durations
+            .iter()
+            .fold(Duration::zero(), |sum, duration| sum + *duration)
+            / durations.len() as i32
+    };
+    // http workers poll constantly, redis workers are only seen when finishing
+    let seen_within = if redis_transport() {
+        Duration::minutes(30)
+    } else {
+        Duration::minutes(2)
+    };
+    let workers = build_workers::dsl::build_workers
+        .filter(build_workers::dsl::enabled.eq(true))
+        .filter(build_workers::dsl::last_seen.gt(Utc::now() - seen_within))
+        .count()
+        .get_result::<i64>(conn)?;
+    // running state in database lags behind redis until monitor sees it
+    let redis = if redis_transport() {
+        REDIS_QUEUE.read().unwrap().clone()
+    } else {
+        None
+    };
+    let (order, running_count) = match redis {
+        Some((waiting, working)) => (
+            redis_order(&queued, &waiting, &running),
+            working.max(running.len()),
+        ),
+        None => {
+            let queued: Vec<(i32, String, i32)> = queued
+                .into_iter()
+                .map(|(id, submitter, priority, _task)| (id, submitter, priority))
+                .collect();
+            (fair_order(&queued, &running), running.len())
+        }
+    };
+    Ok(QueueSnapshot {
+        order,
+        running: running_count,
+        workers: (workers as usize).max(1),
+        build_duration,
+    })
+}
+
+mod test {
+    #[test]
+    fn interleave_users() {
+        use super::*;
+        let job = |id: i32, submitter: &str, priority: i32| (id, String::from(submitter), priority);
+        let queued = vec![
+            job(1, "alice", 0),
+            job(2, "alice", 0),
+            job(3, "alice", 0),
+            job(4, "bob", 0),
+            job(5, "carol", 0),
+            job(6, "bob", 0),
+            job(7, "ta", 10),
+        ];
+        // carol has one build running already
+        let running = vec![String::from("carol")];
+        assert_eq!(fair_order(&queued, &running), vec![7, 1, 4, 2, 5, 6, 3]);
+    }
+
+    #[test]
+    fn redis_first() {
+        use super::*;
+        let job = |id: i32, submitter: &str, task: &str| {
+            (id, String::from(submitter), 0, Some(String::from(task)))
+        };
+        let queued = vec![
+            job(1, "alice", "a1"),
+            job(2, "alice", "a2"),
+            job(3, "bob", "b1"),
+            job(4, "alice", "a3"),
+        ];
+        // a2 was pushed before b1 was submitted
+        let waiting = vec![String::from("a2"), String::from("gone")];
+        assert_eq!(redis_order(&queued, &waiting, &[]), vec![2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn estimate_rounds() {
+        use super::*;
+        let minute = Duration::minutes(1);
+        // two workers, three jobs ahead: starts after one round
+        let (start, finish) = estimate(3, 2, minute);
+        let wait = start - Utc::now();
+        assert!(wait > Duration::seconds(59) && wait <= minute);
+        assert_eq!(finish - start, minute);
+        let (start, _finish) = estimate(1, 2, minute);
+        assert!(start - Utc::now() <= Duration::zero());
+    }
+}
@@ -13,13 +13,13 @@ use std::fmt::Display;
 use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IOSetting {
     pub mask: Option<String>,
     pub data: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClockSetting {
     pub frequency: u32,
 }
@@ -108,6 +108,50 @@ pub async fn download_s3(file_name: String) -> Option<Bytes> {
     None
 }
 
+/// List files in a plain tar archive as created by frontend
+pub fn read_tar_files(data: &[u8]) -> Vec<(String, String)> {
+    read_tar_entries(data)
+        .into_iter()
+        .map(|(name, content)| (name, String::from_utf8_lossy(content).into_owned()))
+        .collect()
+}
+
+/// List files in a plain tar archive with their raw content
+pub fn read_tar_entries(data: &[u8]) -> Vec<(String, &[u8])> {
+    let mut res = vec![];
+    let mut offset = 0;
+    while offset + 512 <= data.len() {
+        let header = &data[offset..offset + 512];
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let name = String::from_utf8_lossy(&header[0..100]);
+        let name = name.trim_end_matches('\0');
+        let size = String::from_utf8_lossy(&header[124..136]);
+        let size = match usize::from_str_radix(size.trim_matches(|c| c == '\0' || c == ' '), 8) {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+        let start = offset + 512;
+        let content = match data.get(start..start + size) {
+            Some(content) => content,
+            None => break,
+        };
+        res.push((String::from(name), content));
+        // content is padded to 512 bytes
+        offset = start + (size + 511) / 512 * 512;
+    }
+    res
+}
+
+/// Find a file by suffix of its name in a plain tar archive
+pub fn read_tar_file(data: &[u8], suffix: &str) -> Option<String> {
+    read_tar_files(data)
+        .into_iter()
+        .find(|(name, _content)| name.ends_with(suffix))
+        .map(|(_name, content)| content)
+}
+
 #[track_caller]
 pub fn err<T: Display>(err: T) -> Error {
     let error_token = generate_uuid();
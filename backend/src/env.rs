@@ -10,6 +10,12 @@ pub struct Env {
     pub board_pass: String,
     pub metric_auth: String,
     pub allow_anonymous_ws_user: bool,
+    pub max_sessions_per_user: usize,
+    // board health
+    pub board_max_program_failure_ratio: f64,
+    pub board_min_program_attempts: u64,
+    pub board_max_reconnects_per_hour: u64,
+    pub board_max_rtt_ms: u64,
     // s3
     pub s3_endpoint: String,
     pub s3_bucket: String,
@@ -20,6 +26,13 @@ pub struct Env {
     pub redis_url: String,
     pub redis_waiting_queue: String,
     pub redis_working_queue: String,
+    // build tasks, "redis" or "http"
+    pub build_transport: String,
+    pub build_lease_secs: u64,
+    // tasks building longer are restarted
+    pub build_timeout_secs: u64,
+    // restarts of a job before giving up
+    pub build_max_retries: i32,
     // sentry
     pub sentry_url: Option<String>,
     // portal
@@ -39,6 +52,26 @@ fn get_env() -> Env {
         board_pass: var("BOARD_PASS").expect("BOARD_PASS"),
         metric_auth: var("METRIC_AUTH").expect("METRIC_AUTH"),
         allow_anonymous_ws_user: var("ALLOW_ANONYMOUS_WS_USER").is_ok(),
+        max_sessions_per_user: var("MAX_SESSIONS_PER_USER")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3),
+        board_max_program_failure_ratio: var("BOARD_MAX_PROGRAM_FAILURE_RATIO")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.5),
+        board_min_program_attempts: var("BOARD_MIN_PROGRAM_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5),
+        board_max_reconnects_per_hour: var("BOARD_MAX_RECONNECTS_PER_HOUR")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10),
+        board_max_rtt_ms: var("BOARD_MAX_RTT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2000),
         s3_endpoint: var("S3_ENDPOINT").expect("S3_ENDPOINT"),
         s3_bucket: var("S3_BUCKET").expect("S3_BUCKET"),
         s3_key: var("S3_KEY").expect("S3_KEY"),
@@ -47,6 +80,19 @@ fn get_env() -> Env {
         redis_url: var("REDIS_URL").expect("REDIS_URL"),
         redis_waiting_queue: var("REDIS_WAITING_QUEUE").expect("jielabs-waiting"),
         redis_working_queue: var("REDIS_WORKING_QUEUE").expect("jielabs-working"),
+        build_transport: var("BUILD_TRANSPORT").unwrap_or_else(|_| "redis".to_owned()),
+        build_lease_secs: var("BUILD_LEASE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60),
+        build_timeout_secs: var("BUILD_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(900),
+        build_max_retries: var("BUILD_MAX_RETRIES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2),
         sentry_url: var("SENTRY_URL").ok(),
         portal: var("PORTAL")
             .unwrap_or_else(|_| "https://lab.cs.tsinghua.edu.cn/portal".to_owned()),
@@ -0,0 +1,126 @@
+use crate::schema::configs;
+use crate::DbConnection;
+use diesel::prelude::*;
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Canary,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareRelease {
+    pub version: String,
+    pub url: String,
+    pub hash: String,
+}
+
+/// Stored in configs under key "firmware"
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FirmwareRollout {
+    pub stable: Option<FirmwareRelease>,
+    pub canary: Option<FirmwareRelease>,
+    // kept for rollback of stable channel
+    pub previous_stable: Option<FirmwareRelease>,
+    // percentage of unassigned boards in canary channel
+    pub canary_percentage: u8,
+    // board remote -> channel
+    pub assignments: HashMap<String, Channel>,
+    // when paused, only explicitly assigned boards get canary
+    pub paused: bool,
+}
+
+impl FirmwareRollout {
+    pub fn channel_for(&self, remote: &str) -> Channel {
+        if let Some(channel) = self.assignments.get(remote) {
+            return *channel;
+        }
+        if !self.paused && self.canary_percentage > 0 {
+            // stable bucket for each board
+            let hash = digest::digest(&digest::SHA256, remote.as_bytes());
+            let bucket = u16::from_be_bytes([hash.as_ref()[0], hash.as_ref()[1]]) % 100;
+            if bucket < self.canary_percentage as u16 {
+                return Channel::Canary;
+            }
+        }
+        Channel::Stable
+    }
+
+    pub fn release_for(&self, remote: &str) -> Option<&FirmwareRelease> {
+        match self.channel_for(remote) {
+            Channel::Canary => self.canary.as_ref().or(self.stable.as_ref()),
+            Channel::Stable => self.stable.as_ref(),
+        }
+    }
+
+    pub fn publish(&mut self, channel: Channel, release: FirmwareRelease) {
+        match channel {
+            Channel::Stable => {
+                if self.stable.as_ref() != Some(&release) {
+                    self.previous_stable = self.stable.take();
+                }
+                self.stable = Some(release);
+            }
+            Channel::Canary => {
+                self.canary = Some(release);
+            }
+        }
+    }
+
+    /// Returns false if there is nothing to roll back to
+    pub fn rollback(&mut self, channel: Channel) -> bool {
+        match channel {
+            Channel::Stable => {
+                if let Some(previous) = self.previous_stable.take() {
+                    self.stable = Some(previous);
+                    true
+                } else {
+                    false
+                }
+            }
+            Channel::Canary => self.canary.take().is_some(),
+        }
+    }
+}
+
+pub fn load_rollout(conn: &DbConnection) -> QueryResult<FirmwareRollout> {
+    let value = configs::dsl::configs
+        .select(configs::dsl::value)
+        .filter(configs::dsl::key.eq("firmware"))
+        .first::<Option<String>>(conn)
+        .optional()?;
+    if let Some(Some(value)) = value {
+        if let Ok(rollout) = serde_json::from_str(&value) {
+            return Ok(rollout);
+        }
+    }
+    // fallback to single version set before rollout channels
+    let value = configs::dsl::configs
+        .select(configs::dsl::value)
+        .filter(configs::dsl::key.eq("version"))
+        .first::<Option<String>>(conn)
+        .optional()?;
+    let mut rollout = FirmwareRollout::default();
+    if let Some(Some(value)) = value {
+        rollout.stable = serde_json::from_str(&value).ok();
+    }
+    Ok(rollout)
+}
+
+pub fn save_rollout(conn: &DbConnection, rollout: &FirmwareRollout) -> QueryResult<usize> {
+    let value = serde_json::to_string(rollout).expect("to json");
+    let kv = (
+        configs::dsl::key.eq("firmware"),
+        configs::dsl::value.eq(&value),
+    );
+    diesel::insert_into(configs::table)
+        .values(kv)
+        .on_conflict(configs::dsl::key)
+        .do_update()
+        .set(kv)
+        .execute(conn)
+}
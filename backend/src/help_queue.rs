@@ -0,0 +1,233 @@
+use crate::presence::{get_presence, NotifyUserByName};
+use crate::ws_user::{NotifyUser, StartHelp, WSUser, WSUserMessageS2U};
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelpMode {
+    Watch,
+    Takeover,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HelpEntry {
+    pub id: u64,
+    pub user_name: String,
+    pub class: Option<String>,
+    pub note: String,
+    pub job_id: Option<i32>,
+    pub board: Option<String>,
+    pub raised_at: DateTime<Utc>,
+    pub claimed_by: Option<String>,
+}
+
+struct Subscriber {
+    addr: Addr<WSUser>,
+    // all classes if None
+    class: Option<String>,
+}
+
+/// Students waiting for help, ordered by time of raising hand
+#[derive(Default)]
+pub struct HelpQueueActor {
+    next_id: u64,
+    entries: Vec<HelpEntry>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl actix::Supervised for HelpQueueActor {}
+
+impl SystemService for HelpQueueActor {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("help queue service is up");
+    }
+}
+
+impl Actor for HelpQueueActor {
+    type Context = Context<Self>;
+}
+
+impl HelpQueueActor {
+    fn queue_of(&self, class: &Option<String>) -> Vec<HelpEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| class.is_none() || &entry.class == class)
+            .cloned()
+            .collect()
+    }
+
+    /// Push new state to the student and staff watching the class
+    fn notify(&mut self, entry: &HelpEntry, removed: bool) {
+        get_presence().do_send(NotifyUserByName {
+            user_name: entry.user_name.clone(),
+            message: WSUserMessageS2U::HelpStatus(if removed { None } else { Some(entry.clone()) }),
+        });
+        self.subscribers.retain(|sub| sub.addr.connected());
+        for sub in &self.subscribers {
+            if sub.class.is_none() || sub.class == entry.class {
+                sub.addr.do_send(NotifyUser(WSUserMessageS2U::HelpQueue(
+                    self.queue_of(&sub.class),
+                )));
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RaiseHand {
+    pub user_name: String,
+    pub class: Option<String>,
+    pub note: String,
+    pub job_id: Option<i32>,
+    pub board: Option<String>,
+}
+
+impl Handler<RaiseHand> for HelpQueueActor {
+    type Result = ();
+
+    fn handle(&mut self, req: RaiseHand, _ctx: &mut Context<Self>) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.user_name == req.user_name)
+        {
+            // update note and keep position in queue
+            entry.note = req.note;
+            entry.job_id = req.job_id;
+            entry.board = req.board;
+            let entry = entry.clone();
+            self.notify(&entry, false);
+            return;
+        }
+        self.next_id += 1;
+        let entry = HelpEntry {
+            id: self.next_id,
+            user_name: req.user_name,
+            class: req.class,
+            note: req.note,
+            job_id: req.job_id,
+            board: req.board,
+            raised_at: Utc::now(),
+            claimed_by: None,
+        };
+        info!("user {} raised hand", entry.user_name);
+        self.entries.push(entry.clone());
+        self.notify(&entry, false);
+    }
+}
+
+/// Student lowers hand, or staff marks the entry as resolved
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResolveHelp {
+    pub user_name: String,
+    pub is_staff: bool,
+    // the entry of the student itself if None
+    pub id: Option<u64>,
+}
+
+impl Handler<ResolveHelp> for HelpQueueActor {
+    type Result = ();
+
+    fn handle(&mut self, req: ResolveHelp, _ctx: &mut Context<Self>) {
+        let index = self.entries.iter().position(|entry| match req.id {
+            Some(id) => entry.id == id && (req.is_staff || entry.user_name == req.user_name),
+            None => entry.user_name == req.user_name,
+        });
+        if let Some(index) = index {
+            let entry = self.entries.remove(index);
+            info!(
+                "help of user {} resolved by {}",
+                entry.user_name, req.user_name
+            );
+            self.notify(&entry, true);
+        }
+    }
+}
+
+/// Last session of the user has closed, drop the user from the queue
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UserOffline {
+    pub user_name: String,
+}
+
+impl Handler<UserOffline> for HelpQueueActor {
+    type Result = ();
+
+    fn handle(&mut self, req: UserOffline, _ctx: &mut Context<Self>) {
+        let (removed, entries) = self
+            .entries
+            .drain(..)
+            .partition(|entry| entry.user_name == req.user_name);
+        self.entries = entries;
+        for entry in removed {
+            info!("help of user {} dropped when offline", entry.user_name);
+            self.notify(&entry, true);
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClaimHelp {
+    pub user: Addr<WSUser>,
+    pub user_name: String,
+    pub id: u64,
+    pub mode: Option<HelpMode>,
+}
+
+impl Handler<ClaimHelp> for HelpQueueActor {
+    type Result = ();
+
+    fn handle(&mut self, req: ClaimHelp, _ctx: &mut Context<Self>) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == req.id) {
+            if entry.claimed_by.is_some() && entry.claimed_by.as_ref() != Some(&req.user_name) {
+                return;
+            }
+            entry.claimed_by = Some(req.user_name.clone());
+            let entry = entry.clone();
+            info!(
+                "help of user {} claimed by {}",
+                entry.user_name, req.user_name
+            );
+            if let Some(mode) = req.mode {
+                req.user.do_send(StartHelp {
+                    target: entry.user_name.clone(),
+                    mode,
+                });
+            }
+            self.notify(&entry, false);
+        }
+    }
+}
+
+/// Staff subscribes to queue changes, current queue is sent at once
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchHelpQueue {
+    pub user: Addr<WSUser>,
+    pub class: Option<String>,
+}
+
+impl Handler<WatchHelpQueue> for HelpQueueActor {
+    type Result = ();
+
+    fn handle(&mut self, req: WatchHelpQueue, _ctx: &mut Context<Self>) {
+        req.user.do_send(NotifyUser(WSUserMessageS2U::HelpQueue(
+            self.queue_of(&req.class),
+        )));
+        self.subscribers.retain(|sub| sub.addr != req.user);
+        self.subscribers.push(Subscriber {
+            addr: req.user,
+            class: req.class,
+        });
+    }
+}
+
+pub fn get_help_queue() -> Addr<HelpQueueActor> {
+    HelpQueueActor::from_registry()
+}
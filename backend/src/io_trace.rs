@@ -0,0 +1,223 @@
+use crate::common::IOSetting;
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// stop recording silently beyond this, about 10 minutes of busy IO
+const MAX_TRACE_EVENTS: usize = 100000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IOTraceKind {
+    SetIOOutput,
+    SetIODirection,
+    ReportIOChange,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IOTraceEvent {
+    pub time: DateTime<Utc>,
+    pub kind: IOTraceKind,
+    pub setting: IOSetting,
+}
+
+/// IO trace being recorded in a board session
+pub struct IOTraceRecording {
+    pub started_at: DateTime<Utc>,
+    pub events: Vec<IOTraceEvent>,
+}
+
+impl IOTraceRecording {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, kind: IOTraceKind, setting: &IOSetting) {
+        if self.events.len() < MAX_TRACE_EVENTS {
+            self.events.push(IOTraceEvent {
+                time: Utc::now(),
+                kind,
+                setting: setting.clone(),
+            });
+        }
+    }
+}
+
+/// Map IO index to signal name, from constraint assignments
+pub fn signal_names(constraints: Option<&str>) -> BTreeMap<usize, String> {
+    let mut names = BTreeMap::new();
+    // directions in job metadata are keyed by board pin, which only the board
+    // config maps to IO index, so rely on the index frontend annotates each
+    // assignment with as "# sig -> label(pin)<index>"
+    if let Some(constraints) = constraints {
+        for line in constraints.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 4 || parts[0] != "#" {
+                continue;
+            }
+            let last = parts[parts.len() - 1];
+            if let (Some(begin), Some(end)) = (last.rfind('<'), last.rfind('>')) {
+                if let Some(Ok(index)) = last.get(begin + 1..end).map(|s| s.parse::<usize>()) {
+                    names.insert(index, String::from(parts[1]));
+                }
+            }
+        }
+    }
+    names
+}
+
+// printable identifiers of vcd variables
+fn vcd_id(mut index: usize) -> String {
+    let mut res = String::new();
+    loop {
+        res.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            break;
+        }
+    }
+    res
+}
+
+fn apply_setting(values: &mut Vec<u8>, setting: &IOSetting) {
+    if let Some(data) = &setting.data {
+        for (i, bit) in data.bytes().enumerate() {
+            let masked = match &setting.mask {
+                Some(mask) => mask.as_bytes().get(i) == Some(&b'1'),
+                None => true,
+            };
+            if masked && i < values.len() {
+                values[i] = bit;
+            }
+        }
+    }
+}
+
+/// Generate vcd file of io levels and directions, in microseconds
+pub fn to_vcd(
+    started_at: DateTime<Utc>,
+    events: &[IOTraceEvent],
+    names: &BTreeMap<usize, String>,
+) -> String {
+    let mut names = names.clone();
+    if names.is_empty() {
+        // no metadata, dump every io ever touched
+        let width = events
+            .iter()
+            .filter_map(|event| event.setting.data.as_ref())
+            .map(|data| data.len())
+            .max()
+            .unwrap_or(0);
+        for index in 0..width {
+            names.insert(index, format!("io{}", index));
+        }
+    }
+    let width = names.keys().max().map(|max| max + 1).unwrap_or(0);
+    let signals: Vec<(usize, String)> = names.into_iter().collect();
+
+    let mut res = String::new();
+    writeln!(res, "$date\n\t{}\n$end", started_at.to_rfc3339()).unwrap();
+    writeln!(res, "$version\n\tJieLabs\n$end").unwrap();
+    writeln!(res, "$timescale 1us $end").unwrap();
+    for (scope, base) in &[("io", 0), ("direction", signals.len())] {
+        writeln!(res, "$scope module {} $end", scope).unwrap();
+        for (i, (_index, name)) in signals.iter().enumerate() {
+            let name = name.replace(' ', "_");
+            writeln!(res, "$var wire 1 {} {} $end", vcd_id(base + i), name).unwrap();
+        }
+        writeln!(res, "$upscope $end").unwrap();
+    }
+    writeln!(res, "$enddefinitions $end").unwrap();
+
+    let mut levels = vec![b'x'; width];
+    let mut directions = vec![b'x'; width];
+    let mut dumped: Option<(Vec<u8>, Vec<u8>)> = None;
+    let mut last_time = None;
+    for event in events.iter().map(Some).chain(std::iter::once(None)) {
+        let time = event.map(|event| {
+            (event.time - started_at)
+                .num_microseconds()
+                .unwrap_or(0)
+                .max(0)
+        });
+        // dump changes once all events at the same time are applied
+        if time != last_time {
+            if let Some(last_time) = last_time {
+                let mut changes = String::new();
+                for (i, (index, _name)) in signals.iter().enumerate() {
+                    let (old_level, old_direction) = match &dumped {
+                        Some((level, direction)) => (Some(level[*index]), Some(direction[*index])),
+                        None => (None, None),
+                    };
+                    if old_level != Some(levels[*index]) {
+                        writeln!(changes, "{}{}", levels[*index] as char, vcd_id(i)).unwrap();
+                    }
+                    if old_direction != Some(directions[*index]) {
+                        writeln!(
+                            changes,
+                            "{}{}",
+                            directions[*index] as char,
+                            vcd_id(signals.len() + i)
+                        )
+                        .unwrap();
+                    }
+                }
+                if dumped.is_none() {
+                    writeln!(res, "#{}\n$dumpvars\n{}$end", last_time, changes).unwrap();
+                } else if !changes.is_empty() {
+                    write!(res, "#{}\n{}", last_time, changes).unwrap();
+                }
+                dumped = Some((levels.clone(), directions.clone()));
+            }
+            last_time = time;
+        }
+        if let Some(event) = event {
+            match event.kind {
+                IOTraceKind::SetIOOutput | IOTraceKind::ReportIOChange => {
+                    apply_setting(&mut levels, &event.setting)
+                }
+                IOTraceKind::SetIODirection => apply_setting(&mut directions, &event.setting),
+            }
+        }
+    }
+    res
+}
+
+mod test {
+    #[test]
+    fn vcd_from_trace() {
+        use super::*;
+        use chrono::Duration;
+        // pin 5 is wired to io 1
+        let names = signal_names(Some(
+            "# clk <- CLK(0)<0>\nset_location_assignment PIN_A1 -to clk\n\
+             # led -> LED 1(5)<1>\nset_location_assignment PIN_B2 -to led\n",
+        ));
+        assert_eq!(names.get(&0).map(|s| s.as_str()), Some("clk"));
+        assert_eq!(names.get(&1).map(|s| s.as_str()), Some("led"));
+        assert_eq!(names.get(&5), None);
+        let started_at = Utc::now();
+        let event = |ms: i64, kind: IOTraceKind, data: &str| IOTraceEvent {
+            time: started_at + Duration::milliseconds(ms),
+            kind,
+            setting: IOSetting {
+                mask: None,
+                data: Some(String::from(data)),
+            },
+        };
+        let events = vec![
+            event(0, IOTraceKind::SetIODirection, "01"),
+            event(1, IOTraceKind::SetIOOutput, "10"),
+            event(2, IOTraceKind::ReportIOChange, "11"),
+            event(2, IOTraceKind::ReportIOChange, "11"),
+        ];
+        let vcd = to_vcd(started_at, &events, &names);
+        println!("{}", vcd);
+        assert!(vcd.contains("$var wire 1 ! clk $end"));
+        assert!(vcd.contains("#1000\n1!\n0\"\n"));
+        assert!(vcd.ends_with("#2000\n1\"\n"));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::models::Job;
+use crate::schema::jobs;
+use crate::DbConnection;
+use chrono::Utc;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Status of a job, stored as text and guarded by a check constraint
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Success,
+    Failed,
+    SystemError,
+    Cancelled,
+    TimedOut,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Success => "success",
+            JobStatus::Failed => "failed",
+            JobStatus::SystemError => "system_error",
+            JobStatus::Cancelled => "cancelled",
+            JobStatus::TimedOut => "timed_out",
+        }
+    }
+
+    pub fn parse_status(s: &str) -> Option<JobStatus> {
+        Some(match s {
+            "queued" => JobStatus::Queued,
+            "running" => JobStatus::Running,
+            "success" => JobStatus::Success,
+            "failed" => JobStatus::Failed,
+            "system_error" => JobStatus::SystemError,
+            "cancelled" => JobStatus::Cancelled,
+            "timed_out" => JobStatus::TimedOut,
+            _ => return None,
+        })
+    }
+
+    /// Parse status reported by build worker, which may use the legacy names
+    pub fn from_report(s: &str) -> Option<JobStatus> {
+        match s {
+            "Compilation Success" => Some(JobStatus::Success),
+            "Compilation Failed" => Some(JobStatus::Failed),
+            "System Error" => Some(JobStatus::SystemError),
+            _ => JobStatus::parse_status(s),
+        }
+    }
+
+    /// Human readable text shown by frontend, None while not finished
+    pub fn legacy_name(self) -> Option<&'static str> {
+        match self {
+            JobStatus::Queued | JobStatus::Running => None,
+            JobStatus::Success => Some("Compilation Success"),
+            JobStatus::Failed => Some("Compilation Failed"),
+            JobStatus::SystemError => Some("System Error"),
+            JobStatus::Cancelled => Some("Cancelled"),
+            JobStatus::TimedOut => Some("Timed Out"),
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+
+    /// Finished jobs never change, running jobs go back to queue when restarted
+    pub fn can_transition_to(self, to: JobStatus) -> bool {
+        match (self, to) {
+            (JobStatus::Queued, JobStatus::Queued) => false,
+            (JobStatus::Queued, _) => true,
+            (JobStatus::Running, JobStatus::Running) => false,
+            (JobStatus::Running, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for JobStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for JobStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        JobStatus::parse_status(&s).ok_or_else(|| format!("unknown job status {}", s).into())
+    }
+}
+
+/// Move job to a new status, returns false if the transition is not allowed
+/// or the status has been changed concurrently
+pub fn set_job_status(conn: &DbConnection, job: &mut Job, status: JobStatus) -> QueryResult<bool> {
+    if !job.status.can_transition_to(status) {
+        return Ok(false);
+    }
+    let finished_at = if status.is_finished() {
+        Some(Utc::now())
+    } else {
+        None
+    };
+    // lease is only held while running
+    let lease_expires_at = if status == JobStatus::Running {
+        job.lease_expires_at
+    } else {
+        None
+    };
+    let updated = diesel::update(
+        jobs::dsl::jobs
+            .find(job.id)
+            .filter(jobs::dsl::status.eq(job.status)),
+    )
+    .set((
+        jobs::dsl::status.eq(status),
+        jobs::dsl::finished_at.eq(finished_at),
+        jobs::dsl::lease_expires_at.eq(lease_expires_at),
+    ))
+    .execute(conn)?;
+    if updated == 0 {
+        return Ok(false);
+    }
+    job.status = status;
+    job.finished_at = finished_at;
+    job.lease_expires_at = lease_expires_at;
+    Ok(true)
+}
+
+mod test {
+    #[test]
+    fn transitions() {
+        use super::*;
+        let all = [
+            JobStatus::Queued,
+            JobStatus::Running,
+            JobStatus::Success,
+            JobStatus::Failed,
+            JobStatus::SystemError,
+            JobStatus::Cancelled,
+            JobStatus::TimedOut,
+        ];
+        for status in all.iter() {
+            assert_eq!(JobStatus::parse_status(status.as_str()), Some(*status));
+            assert_eq!(status.legacy_name().is_none(), !status.is_finished());
+            if status.is_finished() {
+                assert!(all.iter().all(|to| !status.can_transition_to(*to)));
+            }
+        }
+        assert!(JobStatus::Queued.can_transition_to(JobStatus::Running));
+        assert!(JobStatus::Running.can_transition_to(JobStatus::Queued));
+        assert!(JobStatus::Running.can_transition_to(JobStatus::TimedOut));
+        assert_eq!(
+            JobStatus::from_report("Compilation Failed"),
+            Some(JobStatus::Failed)
+        );
+        assert_eq!(JobStatus::from_report("Compiling"), None);
+    }
+}
@@ -7,17 +7,33 @@ use diesel::r2d2::{ConnectionManager, Pool};
 pub type DbConnection = PgConnection;
 type DbPool = Pool<ConnectionManager<DbConnection>>;
 
+pub mod assignment;
 pub mod board;
+pub mod board_health;
 pub mod board_manager;
+pub mod build_cache;
+pub mod build_log;
+pub mod build_queue;
 pub mod common;
 pub mod env;
 pub mod file;
+pub mod firmware;
+pub mod help_queue;
+pub mod io_trace;
+pub mod job_status;
 pub mod metric;
 pub mod models;
+pub mod presence;
 pub mod schema;
+pub mod self_test;
 pub mod session;
+pub mod similarity;
 pub mod task;
 pub mod task_manager;
+pub mod test_vector;
+pub mod trace;
 pub mod user;
+pub mod worker;
+pub mod ws_admin;
 pub mod ws_board;
 pub mod ws_user;
@@ -1,13 +1,15 @@
 use crate::board_manager::{get_board_manager, BoardInfoList, GetBoardList};
 use crate::common::err;
 use crate::env::ENV;
+use crate::job_status::JobStatus;
+use crate::presence::{get_presence, GetSessions, SessionList};
 use crate::schema::{jobs, users};
 use crate::task_manager::{get_task_manager, GetMetric, GetMetricResponse};
-use crate::ws_user::ONLINE_USERS;
 use crate::DbPool;
 use actix_web::{get, web, Result};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use diesel::prelude::*;
+use std::collections::HashSet;
 use std::time::SystemTime;
 
 #[get("/")]
@@ -32,31 +34,59 @@ async fn get(pool: web::Data<DbPool>, auth: BearerAuth) -> Result<String> {
             .get_result::<i64>(&conn)
             .map_err(err)?;
         let job_compilation_success_count = jobs::dsl::jobs
-            .filter(jobs::dsl::status.eq("Compilation Success"))
+            .filter(jobs::dsl::status.eq(JobStatus::Success))
             .count()
             .get_result::<i64>(&conn)
             .map_err(err)?;
         let job_compilation_failed_count = jobs::dsl::jobs
-            .filter(jobs::dsl::status.eq("Compilation Failed"))
+            .filter(jobs::dsl::status.eq(JobStatus::Failed))
             .count()
             .get_result::<i64>(&conn)
             .map_err(err)?;
         let job_system_error_count = jobs::dsl::jobs
-            .filter(jobs::dsl::status.eq("System Error"))
+            .filter(jobs::dsl::status.eq(JobStatus::SystemError))
             .count()
             .get_result::<i64>(&conn)
             .map_err(err)?;
         let tasks: GetMetricResponse = get_task_manager().send(GetMetric).await.map_err(err)?;
         let boards: BoardInfoList = get_board_manager().send(GetBoardList).await.map_err(err)?;
+        let sessions: SessionList = get_presence()
+            .send(GetSessions { user_name: None })
+            .await
+            .map_err(err)?;
+        let online_session_count = sessions.0.len();
+        let online_user_count = sessions
+            .0
+            .iter()
+            .map(|session| &session.user_name)
+            .collect::<HashSet<_>>()
+            .len();
         let board_count = boards.0.len();
         let assigned_board_count = boards
             .0
             .iter()
             .filter(|board| board.connected_user.is_some())
             .count();
+        let health = boards.0.iter().filter_map(|board| board.health.as_ref());
+        let maintenance_board_count = health
+            .clone()
+            .filter(|health| health.maintenance.is_some())
+            .count();
+        let board_program_success_count: u64 =
+            health.clone().map(|health| health.program_success).sum();
+        let board_program_failure_count: u64 =
+            health.clone().map(|health| health.program_failure).sum();
+        let board_reconnect_count: u64 = health.clone().map(|health| health.reconnect_count).sum();
+        let board_io_update_rate: f64 = health.clone().map(|health| health.io_update_rate).sum();
+        let rtts: Vec<u64> = health.filter_map(|health| health.avg_rtt_ms).collect();
+        let board_avg_rtt_ms = if rtts.is_empty() {
+            0
+        } else {
+            rtts.iter().sum::<u64>() / rtts.len() as u64
+        };
         Ok(format!(
-            "jielabsweb-backend user-count={}i,live-user-count={}i,online-user-count={}i,job-count={}i,job-compilation-success-count={}i,job-compilation-failed-count={}i,job-system-error-count={}i,waiting-len={}i,working-len={}i,board-count={}i,assigned-board-count={}i {}",
-            user_count, live_user_count, ONLINE_USERS.lock().unwrap().len(), job_count, job_compilation_success_count, job_compilation_failed_count, job_system_error_count, tasks.len_waiting, tasks.len_working, board_count, assigned_board_count, timestamp
+            "jielabsweb-backend user-count={}i,live-user-count={}i,online-user-count={}i,online-session-count={}i,job-count={}i,job-compilation-success-count={}i,job-compilation-failed-count={}i,job-system-error-count={}i,waiting-len={}i,working-len={}i,board-count={}i,assigned-board-count={}i,maintenance-board-count={}i,board-program-success-count={}i,board-program-failure-count={}i,board-reconnect-count={}i,board-io-update-rate={},board-avg-rtt-ms={}i {}",
+            user_count, live_user_count, online_user_count, online_session_count, job_count, job_compilation_success_count, job_compilation_failed_count, job_system_error_count, tasks.len_waiting, tasks.len_working, board_count, assigned_board_count, maintenance_board_count, board_program_success_count, board_program_failure_count, board_reconnect_count, board_io_update_rate, board_avg_rtt_ms, timestamp
         ))
     } else {
         Ok(format!(""))
@@ -1,4 +1,8 @@
-use crate::schema::{jobs, users};
+use crate::job_status::JobStatus;
+use crate::schema::{
+    assignments, audit_logs, build_workers, io_traces, job_attempts, job_logs, jobs,
+    similarity_reports, submissions, test_runs, users,
+};
 use chrono::{DateTime, Utc};
 
 #[derive(Debug, Queryable, AsChangeset, Identifiable)]
@@ -13,6 +17,17 @@ pub struct User {
     pub last_login: Option<DateTime<Utc>>,
 }
 
+impl User {
+    /// Admins and TAs, who manage assignments and help students
+    pub fn is_staff(&self) -> bool {
+        is_staff_role(&self.role)
+    }
+}
+
+pub fn is_staff_role(role: &str) -> bool {
+    role == "admin" || role == "ta"
+}
+
 #[derive(Debug, Insertable)]
 #[table_name = "users"]
 pub struct NewUser {
@@ -31,12 +46,19 @@ pub struct Job {
     pub submitter: String,
     pub type_: String,
     pub source: String,
-    pub status: Option<String>,
+    pub status: JobStatus,
     pub destination: Option<String>,
     pub metadata: String,
     pub task_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub finished_at: Option<DateTime<Utc>>,
+    pub worker_id: Option<i32>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    pub priority: i32,
+    pub attempts: i32,
+    pub source_hash: Option<String>,
+    pub cache_hit: bool,
+    pub assignment_id: Option<i32>,
 }
 
 #[derive(Debug, Insertable)]
@@ -45,8 +67,202 @@ pub struct NewJob {
     pub submitter: String,
     pub type_: String,
     pub source: String,
-    pub status: Option<String>,
+    pub status: JobStatus,
     pub destination: Option<String>,
     pub metadata: String,
     pub task_id: Option<String>,
+    pub priority: i32,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub source_hash: Option<String>,
+    pub cache_hit: bool,
+    // submitted to the assignment when finished
+    pub assignment_id: Option<i32>,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct AuditLog {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_logs"]
+pub struct NewAuditLog {
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct IoTrace {
+    pub id: i32,
+    pub user_name: String,
+    pub board: String,
+    pub job_id: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub events: String,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "io_traces"]
+pub struct NewIoTrace {
+    pub user_name: String,
+    pub board: String,
+    pub job_id: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub events: String,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "test_runs"]
+pub struct TestRunRecord {
+    pub id: i32,
+    pub user_name: String,
+    pub job_id: i32,
+    pub board: Option<String>,
+    pub script: String,
+    pub passed: Option<bool>,
+    pub report: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "test_runs"]
+pub struct NewTestRun {
+    pub user_name: String,
+    pub job_id: i32,
+    pub script: String,
+}
+
+#[derive(Debug, Queryable, AsChangeset, Identifiable)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Assignment {
+    pub id: i32,
+    pub title: String,
+    pub class: Option<String>,
+    pub deadline: DateTime<Utc>,
+    pub top_entity: Option<String>,
+    pub ports: Option<String>,
+    pub test_script: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "assignments"]
+pub struct NewAssignment {
+    pub title: String,
+    pub class: Option<String>,
+    pub deadline: DateTime<Utc>,
+    pub top_entity: Option<String>,
+    pub ports: Option<String>,
+    pub test_script: Option<String>,
+}
+
+#[derive(Debug, Queryable, AsChangeset, Identifiable)]
+pub struct Submission {
+    pub id: i32,
+    pub assignment_id: i32,
+    pub user_name: String,
+    pub job_id: i32,
+    pub submitted_at: DateTime<Utc>,
+    pub late: bool,
+    pub test_run_id: Option<i32>,
+    pub score: Option<i32>,
+    pub comment: Option<String>,
+    pub graded_by: Option<String>,
+    pub graded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "submissions"]
+pub struct NewSubmission {
+    pub assignment_id: i32,
+    pub user_name: String,
+    pub job_id: i32,
+    pub late: bool,
+    pub test_run_id: Option<i32>,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "similarity_reports"]
+pub struct SimilarityReportRecord {
+    pub id: i32,
+    pub created_by: String,
+    pub assignment_id: Option<i32>,
+    pub class: Option<String>,
+    pub report: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "similarity_reports"]
+pub struct NewSimilarityReport {
+    pub created_by: String,
+    pub assignment_id: Option<i32>,
+    pub class: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct BuildWorker {
+    pub id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "build_workers"]
+pub struct NewBuildWorker {
+    pub name: String,
+    pub token_hash: String,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct JobLog {
+    pub id: i32,
+    pub job_id: i32,
+    pub task_id: String,
+    pub stream: String,
+    pub data: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "job_logs"]
+pub struct NewJobLog {
+    pub job_id: i32,
+    pub task_id: String,
+    pub stream: String,
+    pub data: String,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct JobAttempt {
+    pub id: i32,
+    pub job_id: i32,
+    pub task_id: String,
+    pub worker_id: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "job_attempts"]
+pub struct NewJobAttempt {
+    pub job_id: i32,
+    pub task_id: String,
+    pub worker_id: Option<i32>,
 }
@@ -0,0 +1,184 @@
+use crate::env::ENV;
+use crate::help_queue::{get_help_queue, UserOffline};
+use crate::ws_admin::{publish, AdminEvent};
+use crate::ws_user::{Kick, NotifyUser, WSUser, WSUserMessageS2U};
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use log::*;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_name: String,
+    pub remote: String,
+    pub connected_at: DateTime<Utc>,
+    pub board: Option<String>,
+}
+
+struct SessionStat {
+    addr: Addr<WSUser>,
+    info: SessionInfo,
+}
+
+/// Keeps track of online ws_user connections
+#[derive(Default)]
+pub struct PresenceActor {
+    // user name -> sessions, oldest first
+    sessions: HashMap<String, Vec<SessionStat>>,
+}
+
+impl actix::Supervised for PresenceActor {}
+
+impl SystemService for PresenceActor {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("presence service is up");
+    }
+}
+
+impl Actor for PresenceActor {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Connect {
+    pub id: String,
+    pub user_name: String,
+    pub remote: String,
+    pub addr: Addr<WSUser>,
+}
+
+impl Handler<Connect> for PresenceActor {
+    type Result = ();
+
+    fn handle(&mut self, req: Connect, _ctx: &mut Context<Self>) {
+        publish(AdminEvent::UserOnline {
+            user_name: req.user_name.clone(),
+            remote: req.remote.clone(),
+        });
+        let sessions = self.sessions.entry(req.user_name.clone()).or_default();
+        sessions.push(SessionStat {
+            addr: req.addr,
+            info: SessionInfo {
+                id: req.id,
+                user_name: req.user_name,
+                remote: req.remote,
+                connected_at: Utc::now(),
+                board: None,
+            },
+        });
+        while sessions.len() > ENV.max_sessions_per_user {
+            let oldest = sessions.remove(0);
+            info!(
+                "kicking user {} oldest session {} from {}",
+                oldest.info.user_name, oldest.info.id, oldest.info.remote
+            );
+            publish(AdminEvent::UserKicked {
+                user_name: oldest.info.user_name.clone(),
+                reason: String::from("too many sessions"),
+            });
+            oldest.addr.do_send(Kick(String::from("too many sessions")));
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub id: String,
+    pub user_name: String,
+}
+
+impl Handler<Disconnect> for PresenceActor {
+    type Result = ();
+
+    fn handle(&mut self, req: Disconnect, _ctx: &mut Context<Self>) {
+        if let Some(sessions) = self.sessions.get_mut(&req.user_name) {
+            if let Some(session) = sessions.iter().find(|session| session.info.id == req.id) {
+                publish(AdminEvent::UserOffline {
+                    user_name: req.user_name.clone(),
+                    remote: session.info.remote.clone(),
+                });
+            }
+            sessions.retain(|session| session.info.id != req.id);
+            if sessions.is_empty() {
+                self.sessions.remove(&req.user_name);
+                get_help_queue().do_send(UserOffline {
+                    user_name: req.user_name,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetBoard {
+    pub id: String,
+    pub user_name: String,
+    pub board: Option<String>,
+}
+
+impl Handler<SetBoard> for PresenceActor {
+    type Result = ();
+
+    fn handle(&mut self, req: SetBoard, _ctx: &mut Context<Self>) {
+        if let Some(sessions) = self.sessions.get_mut(&req.user_name) {
+            for session in sessions.iter_mut() {
+                if session.info.id == req.id {
+                    session.info.board = req.board.clone();
+                }
+            }
+        }
+    }
+}
+
+/// Send message to all online sessions of a user
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NotifyUserByName {
+    pub user_name: String,
+    pub message: WSUserMessageS2U,
+}
+
+impl Handler<NotifyUserByName> for PresenceActor {
+    type Result = ();
+
+    fn handle(&mut self, req: NotifyUserByName, _ctx: &mut Context<Self>) {
+        if let Some(sessions) = self.sessions.get(&req.user_name) {
+            for session in sessions {
+                session.addr.do_send(NotifyUser(req.message.clone()));
+            }
+        }
+    }
+}
+
+#[derive(MessageResponse)]
+pub struct SessionList(pub Vec<SessionInfo>);
+
+#[derive(Message)]
+#[rtype(result = "SessionList")]
+pub struct GetSessions {
+    // all users if None
+    pub user_name: Option<String>,
+}
+
+impl Handler<GetSessions> for PresenceActor {
+    type Result = SessionList;
+
+    fn handle(&mut self, req: GetSessions, _ctx: &mut Context<Self>) -> SessionList {
+        let mut res = vec![];
+        for (user_name, sessions) in &self.sessions {
+            if req.user_name.is_none() || req.user_name.as_ref() == Some(user_name) {
+                res.extend(sessions.iter().map(|session| session.info.clone()));
+            }
+        }
+        SessionList(res)
+    }
+}
+
+pub fn get_presence() -> Addr<PresenceActor> {
+    PresenceActor::from_registry()
+}
@@ -1,3 +1,38 @@
+table! {
+    assignments (id) {
+        id -> Int4,
+        title -> Text,
+        class -> Nullable<Text>,
+        deadline -> Timestamptz,
+        top_entity -> Nullable<Text>,
+        ports -> Nullable<Text>,
+        test_script -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    audit_logs (id) {
+        id -> Int4,
+        created_at -> Timestamptz,
+        actor -> Text,
+        action -> Text,
+        target -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+table! {
+    build_workers (id) {
+        id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        enabled -> Bool,
+        created_at -> Timestamptz,
+        last_seen -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     configs (id) {
         id -> Int4,
@@ -6,6 +41,42 @@ table! {
     }
 }
 
+table! {
+    io_traces (id) {
+        id -> Int4,
+        user_name -> Text,
+        board -> Text,
+        job_id -> Nullable<Int4>,
+        started_at -> Timestamptz,
+        finished_at -> Timestamptz,
+        events -> Text,
+    }
+}
+
+table! {
+    job_attempts (id) {
+        id -> Int4,
+        job_id -> Int4,
+        task_id -> Text,
+        worker_id -> Nullable<Int4>,
+        started_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+        outcome -> Nullable<Text>,
+        message -> Nullable<Text>,
+    }
+}
+
+table! {
+    job_logs (id) {
+        id -> Int4,
+        job_id -> Int4,
+        task_id -> Text,
+        stream -> Text,
+        data -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     jobs (id) {
         id -> Int4,
@@ -13,12 +84,62 @@ table! {
         #[sql_name = "type"]
         type_ -> Text,
         source -> Text,
-        status -> Nullable<Text>,
+        status -> Text,
         destination -> Nullable<Text>,
         metadata -> Text,
         task_id -> Nullable<Text>,
         created_at -> Timestamptz,
         finished_at -> Nullable<Timestamptz>,
+        worker_id -> Nullable<Int4>,
+        lease_expires_at -> Nullable<Timestamptz>,
+        priority -> Int4,
+        attempts -> Int4,
+        source_hash -> Nullable<Text>,
+        cache_hit -> Bool,
+        assignment_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    similarity_reports (id) {
+        id -> Int4,
+        created_by -> Text,
+        assignment_id -> Nullable<Int4>,
+        class -> Nullable<Text>,
+        report -> Nullable<Text>,
+        created_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+        error -> Nullable<Text>,
+    }
+}
+
+table! {
+    submissions (id) {
+        id -> Int4,
+        assignment_id -> Int4,
+        user_name -> Text,
+        job_id -> Int4,
+        submitted_at -> Timestamptz,
+        late -> Bool,
+        test_run_id -> Nullable<Int4>,
+        score -> Nullable<Int4>,
+        comment -> Nullable<Text>,
+        graded_by -> Nullable<Text>,
+        graded_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    test_runs (id) {
+        id -> Int4,
+        user_name -> Text,
+        job_id -> Int4,
+        board -> Nullable<Text>,
+        script -> Text,
+        passed -> Nullable<Bool>,
+        report -> Nullable<Text>,
+        created_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -35,4 +156,24 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(configs, jobs, users,);
+joinable!(job_attempts -> build_workers (worker_id));
+joinable!(job_attempts -> jobs (job_id));
+joinable!(job_logs -> jobs (job_id));
+joinable!(jobs -> assignments (assignment_id));
+joinable!(jobs -> build_workers (worker_id));
+joinable!(submissions -> assignments (assignment_id));
+
+allow_tables_to_appear_in_same_query!(
+    assignments,
+    audit_logs,
+    build_workers,
+    configs,
+    io_traces,
+    job_attempts,
+    job_logs,
+    jobs,
+    similarity_reports,
+    submissions,
+    test_runs,
+    users,
+);
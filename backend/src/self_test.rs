@@ -0,0 +1,74 @@
+use crate::common::IOSetting;
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelfTestVector {
+    pub output: IOSetting,
+    pub expected: IOSetting,
+}
+
+/// Stored in configs under key "self_test"
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelfTestConfig {
+    // s3 key of reference bitstream, in the same format as build results
+    pub bitstream: String,
+    pub direction: IOSetting,
+    pub vectors: Vec<SelfTestVector>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SelfTestResult {
+    pub time: DateTime<Utc>,
+    // None if still running
+    pub passed: Option<bool>,
+    pub message: String,
+}
+
+pub struct SelfTestRun {
+    pub id: String,
+    pub config: SelfTestConfig,
+    // None when programming
+    pub step: Option<usize>,
+}
+
+/// Check bits of actual against expected, only where mask is 1
+pub fn io_matches(expected: &IOSetting, actual: &IOSetting) -> bool {
+    let expected_data = match &expected.data {
+        Some(data) => data.as_bytes(),
+        None => return true,
+    };
+    let actual_data = match &actual.data {
+        Some(data) => data.as_bytes(),
+        None => return false,
+    };
+    for (i, bit) in expected_data.iter().enumerate() {
+        let masked = match &expected.mask {
+            Some(mask) => mask.as_bytes().get(i) == Some(&b'1'),
+            None => true,
+        };
+        if masked && actual_data.get(i) != Some(bit) {
+            return false;
+        }
+    }
+    true
+}
+
+mod test {
+    #[test]
+    fn io_matches() {
+        use super::*;
+        let expected = IOSetting {
+            mask: Some(String::from("0110")),
+            data: Some(String::from("1010")),
+        };
+        let actual = |data: &str| IOSetting {
+            mask: None,
+            data: Some(String::from(data)),
+        };
+        assert!(io_matches(&expected, &actual("0011")));
+        assert!(io_matches(&expected, &actual("1010")));
+        assert!(!io_matches(&expected, &actual("1100")));
+        assert!(!io_matches(&expected, &actual("0")));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::common::{download_s3, err, get_download_url, read_tar_files};
+use crate::models::*;
+use crate::schema::{jobs, similarity_reports, submissions, users};
+use crate::session::get_user;
+use crate::{DbConnection, DbPool};
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+// tokens per fingerprint
+const SHINGLE_LEN: usize = 5;
+const MAX_PAIRS: usize = 100;
+
+// separated by whitespace, vhdl first and then verilog
+const KEYWORDS: &str = "\
+    abs access after alias all and architecture array assert attribute begin block body \
+    buffer bus case component configuration constant downto else elsif end entity exit file \
+    for function generate generic group guarded if impure in inertial inout is label library \
+    linkage literal loop map mod nand new next nor not null of on open or others out package \
+    port postponed procedure process pure range record register reject rem report return rol \
+    ror select severity signal shared sla sll sra srl subtype then to transport type \
+    unaffected units until use variable wait when while with xnor xor std_logic \
+    std_logic_vector rising_edge falling_edge unsigned signed integer boolean \
+    always always_comb always_ff assign automatic default defparam endcase endfunction \
+    endgenerate endmodule endtask enum forever genvar initial inout input int localparam \
+    logic module negedge output parameter posedge reg repeat task typedef wire";
+
+/// Tokens with comments, whitespace and identifiers normalized away
+pub fn normalize(source: &str) -> Vec<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c.is_whitespace() {
+            i += 1;
+        } else if (c == '-' && next == Some('-')) || (c == '/' && next == Some('/')) {
+            // line comment
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            i += 1;
+            tokens.push(String::from("str"));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(String::from("num"));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            // vhdl is case insensitive
+            let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+            if KEYWORDS.split_whitespace().any(|keyword| keyword == word) {
+                tokens.push(word);
+            } else {
+                tokens.push(String::from("id"));
+            }
+        } else {
+            tokens.push(c.to_string());
+            i += 1;
+        }
+    }
+    tokens
+}
+
+fn fingerprints(tokens: &[String]) -> HashSet<u64> {
+    tokens
+        .windows(SHINGLE_LEN)
+        .map(|window| {
+            let mut hasher = DefaultHasher::new();
+            window.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceRef {
+    pub job_id: i32,
+    pub user_name: String,
+    // s3 key of the source archive
+    pub source: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimilarPair {
+    pub similarity: f64,
+    pub first: SourceRef,
+    pub second: SourceRef,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimilarityReport {
+    pub sources: usize,
+    pub pairs: Vec<SimilarPair>,
+    // jobs whose source could not be downloaded
+    #[serde(default)]
+    pub missing: Vec<SourceRef>,
+}
+
+/// Rank pairs of sources by jaccard similarity of fingerprints
+pub fn compare(sources: Vec<(SourceRef, String)>, threshold: f64) -> SimilarityReport {
+    let mut prints: Vec<(SourceRef, HashSet<u64>)> = sources
+        .into_iter()
+        .map(|(source, content)| (source, fingerprints(&normalize(&content))))
+        .filter(|(_source, prints)| !prints.is_empty())
+        .collect();
+    // fingerprints shared by most sources are likely from the given template
+    if prints.len() >= 4 {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for (_source, prints) in &prints {
+            for print in prints {
+                *counts.entry(*print).or_default() += 1;
+            }
+        }
+        let limit = prints.len() / 2;
+        for (_source, prints) in &mut prints {
+            prints.retain(|print| counts[print] <= limit);
+        }
+    }
+    let mut pairs = vec![];
+    for i in 0..prints.len() {
+        for j in i + 1..prints.len() {
+            let (first, a) = &prints[i];
+            let (second, b) = &prints[j];
+            let union = a.union(b).count();
+            if union == 0 {
+                continue;
+            }
+            let similarity = a.intersection(b).count() as f64 / union as f64;
+            if similarity >= threshold {
+                pairs.push(SimilarPair {
+                    similarity,
+                    first: first.clone(),
+                    second: second.clone(),
+                });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+    pairs.truncate(MAX_PAIRS);
+    SimilarityReport {
+        sources: prints.len(),
+        pairs,
+        missing: vec![],
+    }
+}
+
+/// Latest job of each student, from submissions of the assignment or builds
+/// of the class
+fn select_jobs(
+    conn: &DbConnection,
+    assignment_id: Option<i32>,
+    class: Option<String>,
+) -> QueryResult<Vec<Job>> {
+    let names = match class {
+        Some(class) => Some(
+            users::dsl::users
+                .select(users::dsl::user_name)
+                .filter(users::dsl::class.eq(class))
+                .load::<String>(conn)?,
+        ),
+        None => None,
+    };
+    let mut latest: HashMap<String, i32> = HashMap::new();
+    if let Some(assignment_id) = assignment_id {
+        let mut query = submissions::dsl::submissions
+            .select((submissions::dsl::user_name, submissions::dsl::job_id))
+            .filter(submissions::dsl::assignment_id.eq(assignment_id))
+            .order(submissions::dsl::id.asc())
+            .into_boxed();
+        if let Some(names) = &names {
+            query = query.filter(submissions::dsl::user_name.eq_any(names));
+        }
+        latest.extend(query.load::<(String, i32)>(conn)?);
+    } else if let Some(names) = &names {
+        latest.extend(
+            jobs::dsl::jobs
+                .select((jobs::dsl::submitter, jobs::dsl::id))
+                .filter(jobs::dsl::submitter.eq_any(names))
+                .order(jobs::dsl::id.asc())
+                .load::<(String, i32)>(conn)?,
+        );
+    }
+    jobs::dsl::jobs
+        .filter(jobs::dsl::id.eq_any(latest.values().cloned().collect::<Vec<i32>>()))
+        .load::<Job>(conn)
+}
+
+async fn run_report(pool: DbPool, id: i32, jobs: Vec<Job>, threshold: f64) {
+    let mut sources = vec![];
+    let mut missing = vec![];
+    for job in jobs {
+        let source = SourceRef {
+            job_id: job.id,
+            user_name: job.submitter,
+            source: job.source,
+        };
+        if let Some(data) = download_s3(source.source.clone()).await {
+            let content = read_tar_files(&data)
+                .into_iter()
+                .filter(|(name, _content)| !name.ends_with(".qsf"))
+                .map(|(_name, content)| content)
+                .collect::<Vec<String>>()
+                .join("\n");
+            sources.push((source, content));
+        } else {
+            warn!(
+                "similarity report {}: failed to download source of job {}",
+                id, source.job_id
+            );
+            missing.push(source);
+        }
+    }
+    let db = pool.clone();
+    let res = web::block(move || {
+        let mut report = compare(sources, threshold);
+        report.missing = missing;
+        let conn = db.get().map_err(failure::Error::from)?;
+        diesel::update(similarity_reports::dsl::similarity_reports.find(id))
+            .set((
+                similarity_reports::dsl::report
+                    .eq(serde_json::to_string(&report).expect("to json")),
+                similarity_reports::dsl::finished_at.eq(Utc::now()),
+            ))
+            .execute(&conn)
+            .map_err(failure::Error::from)
+    })
+    .await;
+    match res {
+        Ok(_) => info!("similarity report {} finished", id),
+        Err(err) => {
+            warn!("similarity report {} failed: {}", id, err);
+            // finish with error so that it is not shown as running
+            let error = err.to_string();
+            let res = web::block(move || {
+                let conn = pool.get().map_err(failure::Error::from)?;
+                diesel::update(similarity_reports::dsl::similarity_reports.find(id))
+                    .set((
+                        similarity_reports::dsl::error.eq(error),
+                        similarity_reports::dsl::finished_at.eq(Utc::now()),
+                    ))
+                    .execute(&conn)
+                    .map_err(failure::Error::from)
+            })
+            .await;
+            if let Err(err) = res {
+                warn!(
+                    "Error occurred when recording failure of similarity report {}: {}",
+                    id, err
+                );
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SimilarityRequest {
+    assignment_id: Option<i32>,
+    class: Option<String>,
+    threshold: Option<f64>,
+}
+
+#[post("/")]
+async fn start(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<SimilarityRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let body = body.into_inner();
+            if body.assignment_id.is_none() && body.class.is_none() {
+                return Ok(HttpResponse::BadRequest().finish());
+            }
+            let threshold = body.threshold.unwrap_or(0.5);
+            let new_report = NewSimilarityReport {
+                created_by: user.user_name,
+                assignment_id: body.assignment_id,
+                class: body.class.clone(),
+            };
+            let (id, jobs) = web::block(move || {
+                let id = diesel::insert_into(similarity_reports::table)
+                    .values(&new_report)
+                    .returning(similarity_reports::dsl::id)
+                    .get_result::<i32>(&conn)?;
+                let jobs = select_jobs(&conn, body.assignment_id, body.class)?;
+                Ok::<_, diesel::result::Error>((id, jobs))
+            })
+            .await
+            .map_err(err)?;
+            info!("start similarity report {} of {} sources", id, jobs.len());
+            actix::spawn(run_report(pool.get_ref().clone(), id, jobs, threshold));
+            return Ok(HttpResponse::Ok().json(id));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SourceInfo {
+    job_id: i32,
+    user_name: String,
+    src_url: String,
+}
+
+impl From<SourceRef> for SourceInfo {
+    fn from(source: SourceRef) -> SourceInfo {
+        SourceInfo {
+            job_id: source.job_id,
+            user_name: source.user_name,
+            src_url: get_download_url(&source.source),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SimilarPairInfo {
+    similarity: f64,
+    first: SourceInfo,
+    second: SourceInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SimilarityReportInfo {
+    id: i32,
+    created_by: String,
+    assignment_id: Option<i32>,
+    class: Option<String>,
+    created_at: DateTime<Utc>,
+    // None if still running
+    finished_at: Option<DateTime<Utc>>,
+    // set if finished without a report
+    error: Option<String>,
+    sources: Option<usize>,
+    pairs: Option<Vec<SimilarPairInfo>>,
+    missing: Option<Vec<SourceInfo>>,
+}
+
+#[get("/{report_id}")]
+async fn get(sess: Session, pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            if let Ok(record) = similarity_reports::dsl::similarity_reports
+                .find(*path)
+                .first::<SimilarityReportRecord>(&conn)
+            {
+                let report = match &record.report {
+                    Some(report) => Some(serde_json::from_str::<SimilarityReport>(report)?),
+                    None => None,
+                };
+                return Ok(HttpResponse::Ok().json(SimilarityReportInfo {
+                    id: record.id,
+                    created_by: record.created_by,
+                    assignment_id: record.assignment_id,
+                    class: record.class,
+                    created_at: record.created_at,
+                    finished_at: record.finished_at,
+                    error: record.error,
+                    sources: report.as_ref().map(|report| report.sources),
+                    missing: report.as_ref().map(|report| {
+                        report
+                            .missing
+                            .iter()
+                            .cloned()
+                            .map(SourceInfo::from)
+                            .collect()
+                    }),
+                    pairs: report.map(|report| {
+                        report
+                            .pairs
+                            .into_iter()
+                            .map(|pair| SimilarPairInfo {
+                                similarity: pair.similarity,
+                                first: SourceInfo::from(pair.first),
+                                second: SourceInfo::from(pair.second),
+                            })
+                            .collect()
+                    }),
+                }));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+mod test {
+    #[test]
+    fn renamed_copy() {
+        use super::*;
+        let original = "entity mod_top is port (clk: in std_logic; q: out std_logic); end;\n\
+                        architecture a of mod_top is begin\n\
+                        process (clk) begin if rising_edge(clk) then q <= not q; end if; end process;\n\
+                        end architecture;";
+        let renamed = "-- my own work\nENTITY top IS PORT (clock : IN STD_LOGIC;\n\
+                       result : OUT STD_LOGIC); END;\n\
+                       architecture behav of top is begin\n\
+                       process (clock) begin if rising_edge(clock) then result <= not result; end if; end process;\n\
+                       end architecture;";
+        assert_eq!(normalize(original), normalize(renamed));
+        let source = |job_id: i32| SourceRef {
+            job_id,
+            user_name: format!("user{}", job_id),
+            source: String::new(),
+        };
+        let report = compare(
+            vec![
+                (source(1), String::from(original)),
+                (source(2), String::from(renamed)),
+                (
+                    source(3),
+                    String::from("module m(input a, output b); assign b = ~a; endmodule"),
+                ),
+            ],
+            0.5,
+        );
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(report.pairs[0].first.job_id, 1);
+        assert_eq!(report.pairs[0].second.job_id, 2);
+    }
+}
@@ -1,19 +1,41 @@
-use crate::common::{err, generate_uuid, get_download_url, get_timestamp, get_upload_url};
+use crate::board_manager::{get_board_manager, StartTestRun};
+use crate::build_cache::{cache_key, find_cached_build};
+use crate::build_log::{get_build_log, job_log, CloseJobLog, PublishJobLog, STREAMS};
+use crate::build_queue::{
+    load_build_queue_config, queue_snapshot, save_build_queue_config, BuildQueueConfig,
+    QueueSnapshot,
+};
+use crate::common::{
+    download_s3, err, generate_uuid, get_download_url, get_timestamp, get_upload_url,
+};
+use crate::job_status::{set_job_status, JobStatus};
 use crate::models::*;
-use crate::schema::jobs;
+use crate::presence::{get_presence, NotifyUserByName};
+use crate::schema::{assignments, job_attempts, job_logs, jobs, test_runs};
 use crate::session::get_user;
-use crate::task_manager::{get_task_manager, SubmitBuildTask};
-use crate::DbPool;
+use crate::task_manager::{
+    bind_redis_task, end_attempt, get_task_manager, redis_transport, CancelTask, ClaimTask,
+    LeaseFinished, ReleaseTask, RenewLease, SubmitBuildTask,
+};
+use crate::test_vector::{TestReport, TestScript};
+use crate::worker::authenticate_worker;
+use crate::ws_admin::{publish, AdminEvent};
+use crate::ws_user::WSUserMessageS2U;
+use crate::{DbConnection, DbPool};
 use actix_session::Session;
 use actix_web::{get, post, web, HttpResponse, Result};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use log::*;
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 struct BuildRequest {
     source: String,
     metadata: String,
+    // built for submitting to an assignment
+    assignment_id: Option<i32>,
 }
 
 #[post("/build")]
@@ -26,6 +48,37 @@ async fn build(
     if let (Some(user), conn) = get_user(&sess, conn).await? {
         let body = body.into_inner();
 
+        let config = load_build_queue_config(&conn).map_err(err)?;
+        let is_staff = user.is_staff();
+        if config.max_pending_per_user > 0 && !is_staff {
+            let pending = jobs::dsl::jobs
+                .filter(jobs::dsl::submitter.eq(&user.user_name))
+                .filter(jobs::dsl::status.eq_any(vec![JobStatus::Queued, JobStatus::Running]))
+                .count()
+                .get_result::<i64>(&conn)
+                .map_err(err)?;
+            if pending >= config.max_pending_per_user {
+                return Ok(HttpResponse::TooManyRequests().json("too many pending builds"));
+            }
+        }
+        // the build is submitted to the assignment, which must be open
+        let assignment = match body.assignment_id {
+            Some(id) => assignments::dsl::assignments
+                .find(id)
+                .first::<Assignment>(&conn)
+                .optional()
+                .map_err(err)?
+                .filter(|assignment| assignment.class.is_none() || assignment.class == user.class),
+            None => None,
+        };
+        if let Some(assignment) = &assignment {
+            // constraints are checked when the job is submitted
+            if !is_staff && Utc::now() > assignment.deadline {
+                return Ok(HttpResponse::BadRequest().json("assignment is closed"));
+            }
+        }
+        let priority = config.priority(&user, assignment.as_ref());
+
         let dest = generate_uuid();
         let task_id = generate_uuid();
 
@@ -33,13 +86,19 @@ async fn build(
         let dst_url = get_upload_url(&dest);
 
         let new_job = NewJob {
-            submitter: user.user_name,
+            submitter: user.user_name.clone(),
             type_: String::from("build"),
             source: body.source,
             metadata: body.metadata,
-            status: None,
+            status: JobStatus::Queued,
             destination: Some(dest.clone()),
             task_id: Some(task_id.clone()),
+            priority,
+            finished_at: None,
+            // known when worker downloads the source
+            source_hash: None,
+            cache_hit: false,
+            assignment_id: assignment.as_ref().map(|assignment| assignment.id),
         };
         let job_id = conn
             .transaction::<_, diesel::result::Error, _>(|| {
@@ -59,38 +118,396 @@ async fn build(
             src: src_url,
             dst: dst_url,
             timestamp: get_timestamp(),
+            priority,
+        });
+        notify_job_update(&conn, job_id);
+        publish(AdminEvent::JobSubmitted {
+            id: job_id,
+            submitter: user.user_name,
         });
         return Ok(HttpResponse::Ok().json(job_id));
     }
     Ok(HttpResponse::Forbidden().finish())
 }
 
+#[get("/queue_config")]
+async fn get_queue_config(sess: Session, pool: web::Data<DbPool>) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let config = load_build_queue_config(&conn).map_err(err)?;
+            return Ok(HttpResponse::Ok().json(config));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[post("/queue_config")]
+async fn update_queue_config(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<BuildQueueConfig>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            save_build_queue_config(&conn, &body).map_err(err)?;
+            return Ok(HttpResponse::Ok().json(true));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Wrap up the attempt of a job that has just finished
+fn job_finished(conn: &DbConnection, job: Job, message: Option<&str>) -> QueryResult<()> {
+    let task_id = job.task_id.clone().unwrap_or_default();
+    end_attempt(conn, &task_id, job.status, message)?;
+    get_task_manager().do_send(LeaseFinished { job_id: job.id });
+    get_build_log().do_send(CloseJobLog { job_id: job.id });
+    notify_job_update(conn, job.id);
+    publish(AdminEvent::JobFinished {
+        id: job.id,
+        submitter: job.submitter,
+        status: job.status,
+    });
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SourceRequest {
+    task_id: String,
+    // hash of files in the source archive
+    content_hash: String,
+}
+
+/// Called by build worker after downloading source. If identical source has
+/// been built, the job reuses its result and returns true, the worker should
+/// skip the build then
+#[post("/source")]
+async fn report_source(
+    body: web::Json<SourceRequest>,
+    pool: web::Data<DbPool>,
+    auth: BearerAuth,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    let worker = match authenticate_worker(&conn, auth.token()).map_err(err)? {
+        Some(worker) => worker,
+        None => return Ok(HttpResponse::Forbidden().finish()),
+    };
+    let mut job = match jobs::dsl::jobs
+        .filter(jobs::dsl::task_id.eq(&body.task_id))
+        .first::<Job>(&conn)
+        .optional()
+        .map_err(err)?
+    {
+        Some(job) if !job.status.is_finished() => job,
+        _ => return Ok(HttpResponse::Ok().json(false)),
+    };
+    let owned = match job.worker_id {
+        Some(worker_id) => worker_id == worker.id,
+        None if redis_transport() => bind_redis_task(&conn, &mut job, worker.id).map_err(err)?,
+        None => false,
+    };
+    if !owned {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let key = cache_key(&body.content_hash, &job.metadata);
+    diesel::update(jobs::dsl::jobs.find(job.id))
+        .set(jobs::dsl::source_hash.eq(Some(&key)))
+        .execute(&conn)
+        .map_err(err)?;
+    let (cached, dest) = match find_cached_build(&conn, &key).map_err(err)? {
+        Some(Job {
+            id,
+            destination: Some(dest),
+            ..
+        }) => (id, dest),
+        _ => return Ok(HttpResponse::Ok().json(false)),
+    };
+    let reused = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            if !set_job_status(&conn, &mut job, JobStatus::Success)? {
+                return Ok(false);
+            }
+            diesel::update(jobs::dsl::jobs.find(job.id))
+                .set((
+                    jobs::dsl::destination.eq(Some(&dest)),
+                    jobs::dsl::cache_hit.eq(true),
+                ))
+                .execute(&conn)?;
+            Ok(true)
+        })
+        .map_err(err)?;
+    if !reused {
+        // finished or requeued concurrently
+        return Ok(HttpResponse::Ok().json(false));
+    }
+    info!("task queue: job {} reuses result of job {}", job.id, cached);
+    let message = format!("reused result of job {}", cached);
+    job_finished(&conn, job, Some(&message)).map_err(err)?;
+    Ok(HttpResponse::Ok().json(true))
+}
+
 #[derive(Serialize, Deserialize)]
 struct FinishRequest {
     task_id: String,
     status: String,
 }
 
+/// Called by build worker with its token, only the worker that took the task
+/// can finish it
 #[post("/finish")]
-async fn finish(body: web::Json<FinishRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse> {
+async fn finish(
+    body: web::Json<FinishRequest>,
+    pool: web::Data<DbPool>,
+    auth: BearerAuth,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    let worker = match authenticate_worker(&conn, auth.token()).map_err(err)? {
+        Some(worker) => worker,
+        None => return Ok(HttpResponse::Forbidden().finish()),
+    };
+    let status = match JobStatus::from_report(&body.status) {
+        Some(status) if status.is_finished() => status,
+        _ => return Ok(HttpResponse::BadRequest().json("unknown job status")),
+    };
+    if let Ok(mut job) = jobs::dsl::jobs
+        .filter(jobs::dsl::task_id.eq(&body.task_id))
+        .first::<Job>(&conn)
+    {
+        match job.worker_id {
+            Some(worker_id) if worker_id != worker.id => {
+                return Ok(HttpResponse::Forbidden().finish());
+            }
+            Some(_) => {}
+            // http workers must claim the task first
+            None if !redis_transport() => return Ok(HttpResponse::Forbidden().finish()),
+            // taken from redis queue directly, record the reporting worker and
+            // its attempt in case the monitor has not seen it
+            None if !job.status.is_finished() => {
+                if !bind_redis_task(&conn, &mut job, worker.id).map_err(err)? {
+                    return Ok(HttpResponse::Forbidden().finish());
+                }
+            }
+            None => {}
+        }
+        return match set_job_status(&conn, &mut job, status) {
+            Ok(true) => {
+                job_finished(&conn, job, None).map_err(err)?;
+                Ok(HttpResponse::Ok().json(true))
+            }
+            // already finished or cancelled
+            Ok(false) => Ok(HttpResponse::Ok().json(true)),
+            Err(_) => Ok(HttpResponse::Ok().json(false)),
+        };
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Build worker claims a queued task with a lease, returns null if none
+#[post("/claim")]
+async fn claim(pool: web::Data<DbPool>, auth: BearerAuth) -> Result<HttpResponse> {
     let conn = pool.get().map_err(err)?;
+    if let Some(worker) = authenticate_worker(&conn, auth.token()).map_err(err)? {
+        let lease = get_task_manager()
+            .send(ClaimTask {
+                worker_id: worker.id,
+            })
+            .await
+            .map_err(err)?
+            .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(lease));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct LeaseRequest {
+    task_id: String,
+    // reason of failure
+    message: Option<String>,
+}
+
+/// Renew lease of a running task, returns new expiry or null if the lease is
+/// lost and the worker should abort
+#[post("/heartbeat")]
+async fn heartbeat(
+    body: web::Json<LeaseRequest>,
+    pool: web::Data<DbPool>,
+    auth: BearerAuth,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let Some(worker) = authenticate_worker(&conn, auth.token()).map_err(err)? {
+        let expires = get_task_manager()
+            .send(RenewLease {
+                worker_id: worker.id,
+                task_id: body.task_id.clone(),
+            })
+            .await
+            .map_err(err)?
+            .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(expires));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Worker fails to process a task, e.g. toolchain crashed, and puts it back
+#[post("/fail")]
+async fn fail(
+    body: web::Json<LeaseRequest>,
+    pool: web::Data<DbPool>,
+    auth: BearerAuth,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let Some(worker) = authenticate_worker(&conn, auth.token()).map_err(err)? {
+        warn!(
+            "worker {} fails task {}: {}",
+            worker.name,
+            body.task_id,
+            body.message.as_deref().unwrap_or("")
+        );
+        let res = get_task_manager()
+            .send(ReleaseTask {
+                worker_id: worker.id,
+                task_id: body.task_id.clone(),
+                message: body.message.clone(),
+            })
+            .await
+            .map_err(err)?
+            .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(res));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+// limit of a single chunk of build log
+pub const MAX_LOG_CHUNK: usize = 64 * 1024;
+// limit of the request body, escaping in json may inflate the chunk
+pub const LOG_JSON_LIMIT: usize = 8 * MAX_LOG_CHUNK;
+
+#[derive(Serialize, Deserialize)]
+struct LogRequest {
+    task_id: String,
+    stream: String,
+    data: String,
+}
+
+/// Build worker appends output of a running task, relayed to users watching,
+/// registered with a json limit of `LOG_JSON_LIMIT`
+pub async fn append_log(
+    body: web::Json<LogRequest>,
+    pool: web::Data<DbPool>,
+    auth: BearerAuth,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    let worker = match authenticate_worker(&conn, auth.token()).map_err(err)? {
+        Some(worker) => worker,
+        None => return Ok(HttpResponse::Forbidden().finish()),
+    };
+    if !STREAMS.contains(&body.stream.as_str()) {
+        return Ok(HttpResponse::BadRequest().json("unknown stream"));
+    }
+    if body.data.len() > MAX_LOG_CHUNK {
+        return Ok(HttpResponse::PayloadTooLarge().finish());
+    }
     if let Ok(mut job) = jobs::dsl::jobs
         .filter(jobs::dsl::task_id.eq(&body.task_id))
         .first::<Job>(&conn)
     {
-        if job.status.is_none() {
-            // not finished
-            job.status = Some(body.status.clone());
-            job.finished_at = Some(Utc::now());
-            return Ok(
-                HttpResponse::Ok().json(diesel::update(&job).set(&job).execute(&conn).is_ok())
-            );
+        if job.status.is_finished() {
+            return Ok(HttpResponse::Ok().json(false));
+        }
+        let owned = match job.worker_id {
+            Some(worker_id) => worker_id == worker.id,
+            // taken from redis queue directly, the first worker reporting has it
+            None if redis_transport() => {
+                bind_redis_task(&conn, &mut job, worker.id).map_err(err)?
+            }
+            None => false,
+        };
+        if !owned {
+            return Ok(HttpResponse::Forbidden().finish());
         }
+        diesel::insert_into(job_logs::table)
+            .values(&NewJobLog {
+                job_id: job.id,
+                task_id: body.task_id.clone(),
+                stream: body.stream.clone(),
+                data: body.data.clone(),
+            })
+            .execute(&conn)
+            .map_err(err)?;
+        get_build_log().do_send(PublishJobLog {
+            job_id: job.id,
+            stream: body.stream.clone(),
+            data: body.data.clone(),
+        });
         return Ok(HttpResponse::Ok().json(true));
     }
     Ok(HttpResponse::Forbidden().finish())
 }
 
+#[derive(Serialize, Deserialize)]
+struct AttemptInfo {
+    task_id: String,
+    worker_id: Option<i32>,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    outcome: Option<String>,
+    message: Option<String>,
+}
+
+/// Attempts of a job in order, for debugging stuck or crashing builds
+#[get("/attempts/{job_id}")]
+async fn list_attempts(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let attempts = job_attempts::dsl::job_attempts
+                .filter(job_attempts::dsl::job_id.eq(*path))
+                .order(job_attempts::dsl::id)
+                .load::<JobAttempt>(&conn)
+                .map_err(err)?;
+            let res: Vec<AttemptInfo> = attempts
+                .into_iter()
+                .map(|attempt| AttemptInfo {
+                    task_id: attempt.task_id,
+                    worker_id: attempt.worker_id,
+                    started_at: attempt.started_at,
+                    finished_at: attempt.finished_at,
+                    outcome: attempt.outcome,
+                    message: attempt.message,
+                })
+                .collect();
+            return Ok(HttpResponse::Ok().json(res));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Build log of the latest attempt of a job, by stream
+#[get("/log/{job_id}")]
+async fn get_log(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if let Ok(job) = jobs::dsl::jobs.find(*path).first::<Job>(&conn) {
+            if user.is_staff() || user.user_name == job.submitter {
+                let log = job_log(&conn, &job).map_err(err)?;
+                return Ok(HttpResponse::Ok().json(log));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
 #[derive(Serialize, Deserialize)]
 struct JobListRequest {
     offset: Option<i64>,
@@ -104,17 +521,66 @@ struct JobListResponse {
     jobs: Vec<JobInfo>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct JobInfo {
-    id: i32,
-    submitter: String,
-    type_: String,
-    metadata: String,
-    status: Option<String>,
-    src_url: String,
-    dst_url: Option<String>,
-    created_at: DateTime<Utc>,
-    finished_at: Option<DateTime<Utc>>,
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobInfo {
+    pub id: i32,
+    pub submitter: String,
+    pub type_: String,
+    pub metadata: String,
+    // legacy text shown by frontend, null when not finished
+    pub status: Option<String>,
+    pub state: JobStatus,
+    pub src_url: String,
+    pub dst_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // times taken by workers, including the current one
+    pub attempts: i32,
+    // result reused from an earlier build of the same source
+    pub cache_hit: bool,
+    // position counting from 1, only when queued
+    pub queue_position: Option<usize>,
+    pub estimated_start: Option<DateTime<Utc>>,
+    pub estimated_finish: Option<DateTime<Utc>>,
+}
+
+impl JobInfo {
+    fn with_estimate(job: Job, queue: &QueueSnapshot) -> JobInfo {
+        let (queue_position, estimated_start, estimated_finish) = queue.job_estimate(&job);
+        JobInfo {
+            queue_position,
+            estimated_start,
+            estimated_finish,
+            ..JobInfo::from(job)
+        }
+    }
+}
+
+/// Attach queue estimates, the queue is only inspected if some job is pending
+fn job_infos(conn: &DbConnection, jobs: Vec<Job>) -> QueryResult<Vec<JobInfo>> {
+    if jobs.iter().all(|job| job.status.is_finished()) {
+        return Ok(jobs.into_iter().map(JobInfo::from).collect());
+    }
+    let queue = queue_snapshot(conn)?;
+    Ok(jobs
+        .into_iter()
+        .map(|job| JobInfo::with_estimate(job, &queue))
+        .collect())
+}
+
+/// Push current info of a job to online sessions of its submitter
+pub fn notify_job_update(conn: &DbConnection, job_id: i32) {
+    if let Ok(job) = jobs::dsl::jobs.find(job_id).first::<Job>(conn) {
+        let user_name = job.submitter.clone();
+        if let Ok(mut infos) = job_infos(conn, vec![job]) {
+            if let Some(info) = infos.pop() {
+                get_presence().do_send(NotifyUserByName {
+                    user_name,
+                    message: WSUserMessageS2U::JobUpdate(info),
+                });
+            }
+        }
+    }
 }
 
 impl From<Job> for JobInfo {
@@ -126,11 +592,17 @@ impl From<Job> for JobInfo {
             submitter: job.submitter,
             type_: job.type_,
             metadata: job.metadata,
-            status: job.status,
+            status: job.status.legacy_name().map(String::from),
+            state: job.status,
             src_url,
             dst_url,
             created_at: job.created_at,
             finished_at: job.finished_at,
+            attempts: job.attempts,
+            cache_hit: job.cache_hit,
+            queue_position: None,
+            estimated_start: None,
+            estimated_finish: None,
         }
     }
 }
@@ -202,7 +674,7 @@ async fn list_self(
             return Ok(HttpResponse::Ok().json(JobListResponse {
                 offset,
                 limit,
-                jobs: jobs.into_iter().map(JobInfo::from).collect(),
+                jobs: job_infos(&conn, jobs).map_err(err)?,
             }));
         }
     }
@@ -215,7 +687,170 @@ async fn get(sess: Session, pool: web::Data<DbPool>, path: web::Path<i32>) -> Re
     if let (Some(user), conn) = get_user(&sess, conn).await? {
         if let Ok(job) = jobs::dsl::jobs.find(*path).first::<Job>(&conn) {
             if user.role == "admin" || user.user_name == job.submitter {
-                return Ok(HttpResponse::Ok().json(JobInfo::from(job)));
+                let mut info = job_infos(&conn, vec![job]).map_err(err)?;
+                return Ok(HttpResponse::Ok().json(info.pop()));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+/// Cancel a queued or running job, returns false if it is already finished
+#[post("/cancel/{job_id}")]
+async fn cancel(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if let Ok(mut job) = jobs::dsl::jobs.find(*path).first::<Job>(&conn) {
+            if user.role == "admin" || user.user_name == job.submitter {
+                let res = set_job_status(&conn, &mut job, JobStatus::Cancelled).map_err(err)?;
+                if res {
+                    if let Some(task_id) = &job.task_id {
+                        end_attempt(&conn, task_id, JobStatus::Cancelled, None).map_err(err)?;
+                    }
+                    notify_job_update(&conn, job.id);
+                    get_build_log().do_send(CloseJobLog { job_id: job.id });
+                    get_task_manager().do_send(CancelTask {
+                        job_id: job.id,
+                        task_id: job.task_id.clone(),
+                    });
+                    publish(AdminEvent::JobFinished {
+                        id: job.id,
+                        submitter: job.submitter,
+                        status: job.status,
+                    });
+                }
+                return Ok(HttpResponse::Ok().json(res));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct TestRunRequest {
+    job_id: i32,
+    script: TestScript,
+}
+
+/// Program job to the board allocated to user and run test script, returns
+/// id of the test run if started
+pub async fn start_test_run(
+    conn: &DbConnection,
+    user_name: &str,
+    job: Job,
+    script: TestScript,
+) -> Result<Option<i32>> {
+    let bitstream = match (job.status, job.destination) {
+        (JobStatus::Success, Some(dest)) => download_s3(dest).await,
+        _ => None,
+    };
+    let bitstream = match bitstream {
+        Some(bitstream) => bitstream,
+        None => return Ok(None),
+    };
+    let new_run = NewTestRun {
+        user_name: String::from(user_name),
+        job_id: job.id,
+        script: serde_json::to_string(&script)?,
+    };
+    let id = diesel::insert_into(test_runs::table)
+        .values(&new_run)
+        .returning(test_runs::dsl::id)
+        .get_result::<i32>(conn)
+        .map_err(err)?;
+    let res = get_board_manager()
+        .send(StartTestRun {
+            user_name: String::from(user_name),
+            id,
+            job_id: job.id,
+            script,
+            bitstream,
+        })
+        .await
+        .map_err(err)?;
+    if !res {
+        diesel::delete(test_runs::dsl::test_runs.find(id))
+            .execute(conn)
+            .map_err(err)?;
+        return Ok(None);
+    }
+    Ok(Some(id))
+}
+
+/// Program job to the board allocated to user and run test script
+#[post("/test")]
+async fn test(
+    sess: Session,
+    body: web::Json<TestRunRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        let body = body.into_inner();
+        if let Ok(job) = jobs::dsl::jobs.find(body.job_id).first::<Job>(&conn) {
+            if job.submitter != user.user_name && !user.is_staff() {
+                return Ok(HttpResponse::Forbidden().finish());
+            }
+            if let Err(reason) = body.script.check() {
+                return Ok(HttpResponse::BadRequest().json(reason));
+            }
+            return match start_test_run(&conn, &user.user_name, job, body.script).await? {
+                Some(id) => Ok(HttpResponse::Ok().json(id)),
+                // job not finished, no board allocated or another test is running
+                None => Ok(HttpResponse::Ok().json(false)),
+            };
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct TestRunInfo {
+    id: i32,
+    user_name: String,
+    job_id: i32,
+    board: Option<String>,
+    passed: Option<bool>,
+    report: Option<TestReport>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<TestRunRecord> for TestRunInfo {
+    fn from(run: TestRunRecord) -> TestRunInfo {
+        TestRunInfo {
+            id: run.id,
+            user_name: run.user_name,
+            job_id: run.job_id,
+            board: run.board,
+            passed: run.passed,
+            report: run
+                .report
+                .and_then(|report| serde_json::from_str(&report).ok()),
+            created_at: run.created_at,
+            finished_at: run.finished_at,
+        }
+    }
+}
+
+#[get("/test/{test_id}")]
+async fn get_test(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if let Ok(run) = test_runs::dsl::test_runs
+            .find(*path)
+            .first::<TestRunRecord>(&conn)
+        {
+            if user.is_staff() || user.user_name == run.user_name {
+                return Ok(HttpResponse::Ok().json(TestRunInfo::from(run)));
             }
         }
     }
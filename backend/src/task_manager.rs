@@ -1,20 +1,35 @@
+use crate::build_log::{get_build_log, CloseJobLog};
+use crate::build_queue::{fair_order, update_redis_queue};
 use crate::common::{generate_uuid, get_download_url, get_timestamp, get_upload_url};
 use crate::env::ENV;
+use crate::job_status::{set_job_status, JobStatus};
 use crate::models::*;
-use crate::schema::jobs;
-use crate::DbPool;
+use crate::schema::{build_workers, job_attempts, jobs};
+use crate::task::notify_job_update;
+use crate::ws_admin::{publish, AdminEvent};
+use crate::{DbConnection, DbPool};
 use actix::prelude::*;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use log::*;
 use redis;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
+/// Whether build tasks are pushed to redis, otherwise workers claim leases
+/// over http
+pub fn redis_transport() -> bool {
+    ENV.build_transport != "http"
+}
+
 #[derive(Default)]
 pub struct TaskManagerActor {
     client: Option<redis::Client>,
     conn: Option<redis::Connection>,
     db: Option<DbPool>,
+    // lease expiry timers by job id
+    leases: HashMap<i32, SpawnHandle>,
 }
 
 impl actix::Supervised for TaskManagerActor {}
@@ -25,10 +40,327 @@ impl Actor for TaskManagerActor {
     fn started(&mut self, _ctx: &mut Context<Self>) {}
 }
 
-fn monitor(
-    actor: &mut TaskManagerActor,
-    ctx: &mut Context<TaskManagerActor>,
-) -> Result<(), failure::Error> {
+/// Count a new attempt of a job when a worker starts building it, returns
+/// false if the attempt of this task is already recorded
+pub fn start_attempt(
+    db_conn: &DbConnection,
+    job_id: i32,
+    task_id: &str,
+    worker_id: Option<i32>,
+) -> QueryResult<bool> {
+    let recorded = job_attempts::dsl::job_attempts
+        .filter(job_attempts::dsl::task_id.eq(task_id))
+        .count()
+        .get_result::<i64>(db_conn)?;
+    if recorded > 0 {
+        return Ok(false);
+    }
+    diesel::update(jobs::dsl::jobs.find(job_id))
+        .set(jobs::dsl::attempts.eq(jobs::dsl::attempts + 1))
+        .execute(db_conn)?;
+    diesel::insert_into(job_attempts::table)
+        .values(&NewJobAttempt {
+            job_id,
+            task_id: String::from(task_id),
+            worker_id,
+        })
+        .execute(db_conn)?;
+    Ok(true)
+}
+
+/// Whether the attempt running a task has been building for too long, the
+/// time waiting in queue is not counted
+fn attempt_timed_out(db_conn: &DbConnection, task_id: &str) -> QueryResult<bool> {
+    let started_at = job_attempts::dsl::job_attempts
+        .select(job_attempts::dsl::started_at)
+        .filter(job_attempts::dsl::task_id.eq(task_id))
+        .first::<DateTime<Utc>>(db_conn)
+        .optional()?;
+    Ok(started_at
+        .map(|started_at| {
+            Utc::now() - started_at > chrono::Duration::seconds(ENV.build_timeout_secs as i64)
+        })
+        .unwrap_or(false))
+}
+
+/// Tasks taken from redis queue directly are bound to the first worker that
+/// reports on them, returns false if another worker has it
+pub fn bind_redis_task(db_conn: &DbConnection, job: &mut Job, worker_id: i32) -> QueryResult<bool> {
+    if let Some(id) = job.worker_id {
+        return Ok(id == worker_id);
+    }
+    let task_id = job.task_id.clone().unwrap_or_default();
+    let updated = diesel::update(
+        jobs::dsl::jobs
+            .find(job.id)
+            .filter(jobs::dsl::task_id.eq(&task_id))
+            .filter(jobs::dsl::worker_id.is_null()),
+    )
+    .set(jobs::dsl::worker_id.eq(Some(worker_id)))
+    .execute(db_conn)?;
+    if updated == 0 {
+        // bound concurrently
+        let bound = jobs::dsl::jobs
+            .select(jobs::dsl::worker_id)
+            .find(job.id)
+            .first::<Option<i32>>(db_conn)?;
+        return Ok(bound == Some(worker_id));
+    }
+    job.worker_id = Some(worker_id);
+    // the attempt may have been seen by monitor already
+    if !start_attempt(db_conn, job.id, &task_id, Some(worker_id))? {
+        diesel::update(
+            job_attempts::dsl::job_attempts
+                .filter(job_attempts::dsl::task_id.eq(&task_id))
+                .filter(job_attempts::dsl::worker_id.is_null()),
+        )
+        .set(job_attempts::dsl::worker_id.eq(Some(worker_id)))
+        .execute(db_conn)?;
+    }
+    if job.status == JobStatus::Queued && set_job_status(db_conn, job, JobStatus::Running)? {
+        notify_job_update(db_conn, job.id);
+    }
+    Ok(true)
+}
+
+/// Record how the attempt running a task ends
+pub fn end_attempt(
+    db_conn: &DbConnection,
+    task_id: &str,
+    outcome: JobStatus,
+    message: Option<&str>,
+) -> QueryResult<usize> {
+    diesel::update(
+        job_attempts::dsl::job_attempts
+            .filter(job_attempts::dsl::task_id.eq(task_id))
+            .filter(job_attempts::dsl::finished_at.is_null()),
+    )
+    .set((
+        job_attempts::dsl::finished_at.eq(Some(Utc::now())),
+        job_attempts::dsl::outcome.eq(Some(outcome.as_str())),
+        job_attempts::dsl::message.eq(message),
+    ))
+    .execute(db_conn)
+}
+
+/// Put an unfinished job back to queue with a new task id and destination,
+/// so late reports of the previous attempt are rejected. The job ends with
+/// `outcome` instead when it has used up its retries. Returns false if the
+/// job has been changed concurrently
+fn requeue_job(
+    db_conn: &DbConnection,
+    job: &mut Job,
+    outcome: JobStatus,
+    message: Option<&str>,
+) -> QueryResult<bool> {
+    let old_task_id = job.task_id.clone().unwrap_or_default();
+    if job.attempts > ENV.build_max_retries {
+        if !set_job_status(db_conn, job, outcome)? {
+            return Ok(false);
+        }
+        end_attempt(db_conn, &old_task_id, outcome, message)?;
+        get_build_log().do_send(CloseJobLog { job_id: job.id });
+        notify_job_update(db_conn, job.id);
+        publish(AdminEvent::JobFinished {
+            id: job.id,
+            submitter: job.submitter.clone(),
+            status: outcome,
+        });
+        info!(
+            "task queue: giving up task {} after {} attempts",
+            old_task_id, job.attempts
+        );
+        return Ok(true);
+    }
+    let new_task_id = generate_uuid();
+    let new_dest = generate_uuid();
+    let updated = diesel::update(
+        jobs::dsl::jobs
+            .find(job.id)
+            .filter(jobs::dsl::status.eq_any(vec![JobStatus::Queued, JobStatus::Running]))
+            .filter(jobs::dsl::task_id.eq(&job.task_id)),
+    )
+    .set((
+        jobs::dsl::status.eq(JobStatus::Queued),
+        jobs::dsl::task_id.eq(Some(&new_task_id)),
+        jobs::dsl::destination.eq(Some(&new_dest)),
+        jobs::dsl::worker_id.eq(None::<i32>),
+        jobs::dsl::lease_expires_at.eq(None::<DateTime<Utc>>),
+    ))
+    .execute(db_conn)?;
+    if updated == 0 {
+        return Ok(false);
+    }
+    end_attempt(db_conn, &old_task_id, outcome, message)?;
+    job.status = JobStatus::Queued;
+    job.task_id = Some(new_task_id.clone());
+    job.destination = Some(new_dest);
+    job.worker_id = None;
+    job.lease_expires_at = None;
+    publish(AdminEvent::JobRestarted {
+        id: job.id,
+        task_id: new_task_id.clone(),
+    });
+    notify_job_update(db_conn, job.id);
+    info!(
+        "task queue: requeue task {} -> {}",
+        old_task_id, new_task_id
+    );
+    Ok(true)
+}
+
+fn requeue_expired(db: &DbPool) -> Result<(), failure::Error> {
+    let db_conn = db.get()?;
+    let expired = jobs::dsl::jobs
+        .filter(jobs::dsl::status.eq(JobStatus::Running))
+        .filter(jobs::dsl::lease_expires_at.le(Utc::now()))
+        .load::<Job>(&db_conn)?;
+    for mut job in expired {
+        requeue_job(
+            &db_conn,
+            &mut job,
+            JobStatus::TimedOut,
+            Some("lease expired"),
+        )?;
+    }
+    Ok(())
+}
+
+/// Top up redis waiting queue with queued jobs in fair order, one for each
+/// enabled worker so that none of them is left idle, the rest stay in
+/// database to be pushed in turn
+fn feed_redis(db: &DbPool, conn: &mut redis::Connection) -> Result<(), failure::Error> {
+    let db_conn = db.get()?;
+    let workers = build_workers::dsl::build_workers
+        .filter(build_workers::dsl::enabled.eq(true))
+        .count()
+        .get_result::<i64>(&db_conn)?;
+    let prefetch = (workers as usize).max(1);
+    let mut waiting: Vec<String> = redis::cmd("LRANGE")
+        .arg(&ENV.redis_waiting_queue)
+        .arg("0")
+        .arg("-1")
+        .query(conn)?;
+    let working: Vec<String> = redis::cmd("LRANGE")
+        .arg(&ENV.redis_working_queue)
+        .arg("0")
+        .arg("-1")
+        .query(conn)?;
+    if waiting.len() >= prefetch {
+        update_redis_queue(Some((&waiting, working.len())));
+        return Ok(());
+    }
+    let in_redis: HashSet<String> = waiting
+        .iter()
+        .chain(working.iter())
+        .filter_map(|task| serde_json::from_str::<SubmitBuildTask>(task).ok())
+        .map(|task| task.id)
+        .collect();
+    let queued = jobs::dsl::jobs
+        .select((
+            jobs::dsl::id,
+            jobs::dsl::submitter,
+            jobs::dsl::priority,
+            jobs::dsl::task_id,
+        ))
+        .filter(jobs::dsl::status.eq(JobStatus::Queued))
+        .filter(jobs::dsl::destination.is_not_null())
+        .load::<(i32, String, i32, Option<String>)>(&db_conn)?;
+    let candidates: Vec<(i32, String, i32)> = queued
+        .into_iter()
+        .filter(|(_id, _submitter, _priority, task_id)| match task_id {
+            Some(task_id) => !in_redis.contains(task_id),
+            None => false,
+        })
+        .map(|(id, submitter, priority, _task_id)| (id, submitter, priority))
+        .collect();
+    let running = jobs::dsl::jobs
+        .select(jobs::dsl::submitter)
+        .filter(jobs::dsl::status.eq(JobStatus::Running))
+        .load::<String>(&db_conn)?;
+    for job_id in fair_order(&candidates, &running)
+        .into_iter()
+        .take(prefetch - waiting.len())
+    {
+        let job = jobs::dsl::jobs.find(job_id).first::<Job>(&db_conn)?;
+        let task = SubmitBuildTask {
+            id: job.task_id.unwrap_or_default(),
+            src: get_download_url(&job.source),
+            dst: get_upload_url(&job.destination.unwrap_or_default()),
+            timestamp: get_timestamp(),
+            priority: job.priority,
+        };
+        // workers pop from the right
+        let task_json = serde_json::to_string(&task).expect("to json");
+        redis::cmd("LPUSH")
+            .arg(&ENV.redis_waiting_queue)
+            .arg(&task_json)
+            .query::<()>(conn)?;
+        waiting.insert(0, task_json);
+        info!("task queue: pushing task {} of job {}", task.id, job_id);
+    }
+    update_redis_queue(Some((&waiting, working.len())));
+    Ok(())
+}
+
+impl TaskManagerActor {
+    fn redis_conn(&mut self) -> Result<&mut redis::Connection, failure::Error> {
+        if self.conn.is_none() {
+            let client = self
+                .client
+                .as_ref()
+                .ok_or_else(|| failure::err_msg("no redis client"))?;
+            self.conn = Some(client.get_connection()?);
+        }
+        Ok(self.conn.as_mut().unwrap()) // safe unwrap
+    }
+
+    /// Push queued jobs to redis when workers have taken some
+    fn feed(&mut self) {
+        if !redis_transport() {
+            return;
+        }
+        let db = match &self.db {
+            Some(db) => db.clone(),
+            None => return,
+        };
+        if let Err(err) = self.redis_conn().and_then(|conn| feed_redis(&db, conn)) {
+            warn!("Error occurred when feeding redis queue: {}", err);
+            // reconnect next time
+            self.conn = None;
+            update_redis_queue(None);
+        }
+    }
+
+    fn schedule_lease(&mut self, ctx: &mut Context<Self>, job_id: i32, expires: DateTime<Utc>) {
+        self.cancel_lease(ctx, job_id);
+        let delay = (expires - Utc::now()).to_std().unwrap_or_default();
+        let handle = ctx.run_later(delay, move |actor, _ctx| {
+            actor.leases.remove(&job_id);
+            if let Some(db) = &actor.db {
+                if let Err(err) = requeue_expired(db) {
+                    warn!("Error occurred when requeueing expired tasks: {}", err);
+                }
+            }
+        });
+        self.leases.insert(job_id, handle);
+    }
+
+    fn cancel_lease(&mut self, ctx: &mut Context<Self>, job_id: i32) {
+        if let Some(handle) = self.leases.remove(&job_id) {
+            ctx.cancel_future(handle);
+        }
+    }
+}
+
+fn monitor(actor: &mut TaskManagerActor) -> Result<(), failure::Error> {
+    if !redis_transport() {
+        // leases are requeued by timers, this catches those lost in restart
+        if let Some(db) = &actor.db {
+            requeue_expired(db)?;
+        }
+        return Ok(());
+    }
     if let Some(db) = &actor.db {
         if let Some(client) = &actor.client {
             let conn = if let Some(conn) = &mut actor.conn {
@@ -50,6 +382,30 @@ fn monitor(
                     "task queue: {} waiting, {} working",
                     len_waiting, len_working
                 );
+                // tasks taken by workers are running
+                let working: Vec<String> = redis::cmd("LRANGE")
+                    .arg(&ENV.redis_working_queue)
+                    .arg("0")
+                    .arg("-1")
+                    .query(conn)?;
+                let task_ids: Vec<String> = working
+                    .iter()
+                    .filter_map(|task| serde_json::from_str::<SubmitBuildTask>(task).ok())
+                    .map(|task| task.id)
+                    .collect();
+                let started = diesel::update(
+                    jobs::dsl::jobs
+                        .filter(jobs::dsl::task_id.eq_any(task_ids))
+                        .filter(jobs::dsl::status.eq(JobStatus::Queued)),
+                )
+                .set(jobs::dsl::status.eq(JobStatus::Running))
+                .returning((jobs::dsl::id, jobs::dsl::task_id))
+                .get_results::<(i32, Option<String>)>(&db_conn)?;
+                for (job_id, task_id) in started {
+                    // worker is unknown until it reports
+                    start_attempt(&db_conn, job_id, &task_id.unwrap_or_default(), None)?;
+                    notify_job_update(&db_conn, job_id);
+                }
                 while let Some(last_working) = redis::cmd("LINDEX")
                     .arg(&ENV.redis_working_queue)
                     .arg("-1")
@@ -61,35 +417,32 @@ fn monitor(
                             .filter(jobs::dsl::task_id.eq(&task.id))
                             .first::<Job>(&db_conn)
                         {
-                            if job.status.is_some() {
+                            if job.status.is_finished() {
                                 // done, remove it
                                 redis::cmd("RPOP")
                                     .arg(&ENV.redis_working_queue)
                                     .query(conn)?;
                                 info!("task queue: removing finished task {}", task.id,);
                             } else {
-                                if get_timestamp() - task.timestamp > 5 * 60 {
+                                // attempt starts when monitor or worker sees it
+                                if start_attempt(&db_conn, job.id, &task.id, None)? {
+                                    notify_job_update(&db_conn, job.id);
+                                }
+                                if attempt_timed_out(&db_conn, &task.id)? {
                                     // timeout, assign a new task id and destination
-                                    let new_task_id = generate_uuid();
-                                    let new_dest = generate_uuid();
-                                    job.task_id = Some(new_task_id.clone());
-                                    job.destination = Some(new_dest.clone());
-                                    let src_url = get_download_url(&job.source);
-                                    let dst_url = get_upload_url(&new_dest);
-                                    diesel::update(&job).set(&job).execute(&db_conn)?;
-                                    ctx.address().do_send(SubmitBuildTask {
-                                        id: new_task_id.clone(),
-                                        src: src_url,
-                                        dst: dst_url,
-                                        timestamp: get_timestamp(),
-                                    });
+                                    if !requeue_job(
+                                        &db_conn,
+                                        &mut job,
+                                        JobStatus::TimedOut,
+                                        Some("build timed out"),
+                                    )? {
+                                        // changed concurrently, check again
+                                        continue;
+                                    }
+                                    // pushed again by feed_redis in turn
                                     redis::cmd("RPOP")
                                         .arg(&ENV.redis_working_queue)
                                         .query(conn)?;
-                                    info!(
-                                        "task queue: restarting task {} -> {}",
-                                        task.id, new_task_id
-                                    );
                                 } else {
                                     // no timeout tasks
                                     break;
@@ -110,6 +463,7 @@ fn monitor(
                     }
                 }
             }
+            feed_redis(db, conn)?;
         }
     }
 
@@ -121,8 +475,8 @@ impl SystemService for TaskManagerActor {
         info!("task manager is up");
         let client = redis::Client::open(ENV.redis_url.clone()).expect("redis client");
         self.client = Some(client);
-        ctx.run_interval(Duration::from_secs(10), |actor, ctx| {
-            if let Err(err) = monitor(actor, ctx) {
+        ctx.run_interval(Duration::from_secs(10), |actor, _ctx| {
+            if let Err(err) = monitor(actor) {
                 warn!("Error occurred in task manager: {}", err);
                 sentry::capture_message(
                     &format!("Error occurred in task manager : {}", err),
@@ -130,6 +484,7 @@ impl SystemService for TaskManagerActor {
                 );
                 // close connection and try again
                 actor.conn = None;
+                update_redis_queue(None);
             }
         });
     }
@@ -142,25 +497,20 @@ pub struct SubmitBuildTask {
     pub src: String,
     pub dst: String,
     pub timestamp: u64,
+    // not seen by workers, jobs are pushed by priority then in turns of users
+    #[serde(skip)]
+    pub priority: i32,
 }
 
 impl Handler<SubmitBuildTask> for TaskManagerActor {
     type Result = bool;
 
     fn handle(&mut self, req: SubmitBuildTask, _ctx: &mut Context<Self>) -> bool {
-        if let Some(conn) = self.conn.as_mut() {
-            if redis::cmd("LPUSH")
-                .arg(&ENV.redis_waiting_queue)
-                .arg(serde_json::to_string(&req).expect("to json"))
-                .query::<()>(conn)
-                .is_ok()
-            {
-                return true;
-            }
-        } else {
-            warn!("no redis conn, fail to submit build task");
-        }
-        false
+        // http workers claim the queued job directly, redis queue is fed from
+        // database in fair order
+        debug!("task queue: job of task {} is queued", req.id);
+        self.feed();
+        true
     }
 }
 
@@ -173,7 +523,21 @@ pub struct SetDb {
 impl Handler<SetDb> for TaskManagerActor {
     type Result = ();
 
-    fn handle(&mut self, req: SetDb, _ctx: &mut Context<Self>) {
+    fn handle(&mut self, req: SetDb, ctx: &mut Context<Self>) {
+        if !redis_transport() {
+            // resume lease timers
+            if let Ok(db_conn) = req.db.get() {
+                if let Ok(running) = jobs::dsl::jobs
+                    .filter(jobs::dsl::status.eq(JobStatus::Running))
+                    .load::<Job>(&db_conn)
+                {
+                    for job in running {
+                        let expires = job.lease_expires_at.unwrap_or_else(Utc::now);
+                        self.schedule_lease(ctx, job.id, expires);
+                    }
+                }
+            }
+        }
         self.db = Some(req.db);
     }
 }
@@ -194,7 +558,19 @@ impl Handler<GetMetric> for TaskManagerActor {
     fn handle(&mut self, _req: GetMetric, _ctx: &mut Context<Self>) -> GetMetricResponse {
         let mut len_waiting = 0;
         let mut len_working = 0;
-        if let Some(conn) = self.conn.as_mut() {
+        if !redis_transport() {
+            if let Some(Ok(db_conn)) = self.db.as_ref().map(|db| db.get()) {
+                let count = |status: JobStatus| {
+                    jobs::dsl::jobs
+                        .filter(jobs::dsl::status.eq(status))
+                        .count()
+                        .get_result::<i64>(&db_conn)
+                        .unwrap_or(0) as u64
+                };
+                len_waiting = count(JobStatus::Queued);
+                len_working = count(JobStatus::Running);
+            }
+        } else if let Some(conn) = self.conn.as_mut() {
             len_waiting = redis::cmd("LLEN")
                 .arg(&ENV.redis_waiting_queue)
                 .query(conn)
@@ -211,6 +587,268 @@ impl Handler<GetMetric> for TaskManagerActor {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct TaskLease {
+    pub task_id: String,
+    pub src: String,
+    pub dst: String,
+    pub lease_expires_at: DateTime<Utc>,
+}
+
+/// Worker claims the next queued job in fair order, only when using http
+/// transport
+#[derive(Message)]
+#[rtype(result = "Result<Option<TaskLease>, failure::Error>")]
+pub struct ClaimTask {
+    pub worker_id: i32,
+}
+
+impl Handler<ClaimTask> for TaskManagerActor {
+    type Result = Result<Option<TaskLease>, failure::Error>;
+
+    fn handle(
+        &mut self,
+        req: ClaimTask,
+        ctx: &mut Context<Self>,
+    ) -> Result<Option<TaskLease>, failure::Error> {
+        let db_conn = match (&self.db, redis_transport()) {
+            (Some(db), false) => db.get()?,
+            _ => return Ok(None),
+        };
+        let expires = Utc::now() + chrono::Duration::seconds(ENV.build_lease_secs as i64);
+        let queued = jobs::dsl::jobs
+            .select((jobs::dsl::id, jobs::dsl::submitter, jobs::dsl::priority))
+            .filter(jobs::dsl::status.eq(JobStatus::Queued))
+            .filter(jobs::dsl::task_id.is_not_null())
+            .filter(jobs::dsl::destination.is_not_null())
+            .load::<(i32, String, i32)>(&db_conn)?;
+        let running = jobs::dsl::jobs
+            .select(jobs::dsl::submitter)
+            .filter(jobs::dsl::status.eq(JobStatus::Running))
+            .load::<String>(&db_conn)?;
+        let mut job = None;
+        for job_id in fair_order(&queued, &running) {
+            job = db_conn.transaction::<_, diesel::result::Error, _>(|| {
+                // skip rows being claimed by other workers
+                let job = jobs::dsl::jobs
+                    .find(job_id)
+                    .filter(jobs::dsl::status.eq(JobStatus::Queued))
+                    .for_update()
+                    .skip_locked()
+                    .first::<Job>(&db_conn)
+                    .optional()?;
+                if let Some(job) = &job {
+                    diesel::update(job)
+                        .set((
+                            jobs::dsl::status.eq(JobStatus::Running),
+                            jobs::dsl::worker_id.eq(Some(req.worker_id)),
+                            jobs::dsl::lease_expires_at.eq(Some(expires)),
+                        ))
+                        .execute(&db_conn)?;
+                    start_attempt(
+                        &db_conn,
+                        job.id,
+                        job.task_id.as_deref().unwrap_or_default(),
+                        Some(req.worker_id),
+                    )?;
+                }
+                Ok(job)
+            })?;
+            if let Some(job) = &job {
+                notify_job_update(&db_conn, job.id);
+                break;
+            }
+        }
+        Ok(job.map(|job| {
+            self.schedule_lease(ctx, job.id, expires);
+            info!("task queue: worker {} claims job {}", req.worker_id, job.id);
+            TaskLease {
+                task_id: job.task_id.unwrap_or_default(),
+                src: get_download_url(&job.source),
+                dst: get_upload_url(&job.destination.unwrap_or_default()),
+                lease_expires_at: expires,
+            }
+        }))
+    }
+}
+
+/// Heartbeat of worker, returns new expiry or None if lease is lost
+#[derive(Message)]
+#[rtype(result = "Result<Option<DateTime<Utc>>, failure::Error>")]
+pub struct RenewLease {
+    pub worker_id: i32,
+    pub task_id: String,
+}
+
+impl Handler<RenewLease> for TaskManagerActor {
+    type Result = Result<Option<DateTime<Utc>>, failure::Error>;
+
+    fn handle(
+        &mut self,
+        req: RenewLease,
+        ctx: &mut Context<Self>,
+    ) -> Result<Option<DateTime<Utc>>, failure::Error> {
+        let db_conn = match &self.db {
+            Some(db) => db.get()?,
+            None => return Ok(None),
+        };
+        let expires = Utc::now() + chrono::Duration::seconds(ENV.build_lease_secs as i64);
+        if redis_transport() {
+            // no lease, only tell the worker whether to go on
+            let job = jobs::dsl::jobs
+                .filter(jobs::dsl::task_id.eq(&req.task_id))
+                .first::<Job>(&db_conn)
+                .optional()?;
+            let claimed = job.as_ref().map(|job| job.worker_id.is_none());
+            let res = match job {
+                Some(mut job)
+                    if !job.status.is_finished()
+                        && bind_redis_task(&db_conn, &mut job, req.worker_id)?
+                        && !attempt_timed_out(&db_conn, &req.task_id)? =>
+                {
+                    Some(expires)
+                }
+                _ => None,
+            };
+            // first heartbeat of a task just popped, push the next one
+            if claimed == Some(true) && res.is_some() {
+                self.feed();
+            }
+            return Ok(res);
+        }
+        let job = jobs::dsl::jobs
+            .filter(jobs::dsl::task_id.eq(&req.task_id))
+            .filter(jobs::dsl::worker_id.eq(req.worker_id))
+            .filter(jobs::dsl::status.eq(JobStatus::Running))
+            .first::<Job>(&db_conn)
+            .optional()?;
+        if let Some(mut job) = job {
+            if attempt_timed_out(&db_conn, &req.task_id)? {
+                self.cancel_lease(ctx, job.id);
+                requeue_job(
+                    &db_conn,
+                    &mut job,
+                    JobStatus::TimedOut,
+                    Some("build timed out"),
+                )?;
+                return Ok(None);
+            }
+        }
+        let job = diesel::update(
+            jobs::dsl::jobs
+                .filter(jobs::dsl::task_id.eq(&req.task_id))
+                .filter(jobs::dsl::worker_id.eq(req.worker_id))
+                .filter(jobs::dsl::status.eq(JobStatus::Running))
+                .filter(jobs::dsl::lease_expires_at.gt(Utc::now())),
+        )
+        .set(jobs::dsl::lease_expires_at.eq(Some(expires)))
+        .get_result::<Job>(&db_conn)
+        .optional()?;
+        Ok(job.map(|job| {
+            self.schedule_lease(ctx, job.id, expires);
+            expires
+        }))
+    }
+}
+
+/// Worker gives up a task, it is requeued immediately
+#[derive(Message)]
+#[rtype(result = "Result<bool, failure::Error>")]
+pub struct ReleaseTask {
+    pub worker_id: i32,
+    pub task_id: String,
+    pub message: Option<String>,
+}
+
+impl Handler<ReleaseTask> for TaskManagerActor {
+    type Result = Result<bool, failure::Error>;
+
+    fn handle(
+        &mut self,
+        req: ReleaseTask,
+        ctx: &mut Context<Self>,
+    ) -> Result<bool, failure::Error> {
+        let db_conn = match &self.db {
+            Some(db) => db.get()?,
+            None => return Ok(false),
+        };
+        let job = jobs::dsl::jobs
+            .filter(jobs::dsl::task_id.eq(&req.task_id))
+            .filter(jobs::dsl::worker_id.eq(req.worker_id))
+            .filter(jobs::dsl::status.eq(JobStatus::Running))
+            .first::<Job>(&db_conn)
+            .optional()?;
+        if let Some(mut job) = job {
+            self.cancel_lease(ctx, job.id);
+            return Ok(requeue_job(
+                &db_conn,
+                &mut job,
+                JobStatus::SystemError,
+                req.message.as_deref(),
+            )?);
+        }
+        Ok(false)
+    }
+}
+
+/// Job left running state, drop its lease timer
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaseFinished {
+    pub job_id: i32,
+}
+
+impl Handler<LeaseFinished> for TaskManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: LeaseFinished, ctx: &mut Context<Self>) {
+        self.cancel_lease(ctx, req.job_id);
+        // a redis worker is free now
+        self.feed();
+    }
+}
+
+/// Job is cancelled, drop it from waiting queue if still there
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CancelTask {
+    pub job_id: i32,
+    pub task_id: Option<String>,
+}
+
+impl Handler<CancelTask> for TaskManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, req: CancelTask, ctx: &mut Context<Self>) {
+        // http workers learn it from heartbeat
+        self.cancel_lease(ctx, req.job_id);
+        if let (Some(conn), Some(task_id)) = (self.conn.as_mut(), req.task_id) {
+            let waiting: Vec<String> = redis::cmd("LRANGE")
+                .arg(&ENV.redis_waiting_queue)
+                .arg("0")
+                .arg("-1")
+                .query(conn)
+                .unwrap_or_default();
+            for task in waiting {
+                if let Ok(parsed) = serde_json::from_str::<SubmitBuildTask>(&task) {
+                    if parsed.id == task_id {
+                        if let Err(err) = redis::cmd("LREM")
+                            .arg(&ENV.redis_waiting_queue)
+                            .arg("1")
+                            .arg(&task)
+                            .query::<()>(conn)
+                        {
+                            warn!("fail to remove cancelled task {}: {}", task_id, err);
+                        }
+                        info!("task queue: removing cancelled task {}", task_id);
+                    }
+                }
+            }
+        }
+        self.feed();
+    }
+}
+
 pub fn get_task_manager() -> Addr<TaskManagerActor> {
     TaskManagerActor::from_registry()
 }
@@ -0,0 +1,159 @@
+use crate::common::{ClockSetting, IOSetting};
+use serde_derive::{Deserialize, Serialize};
+
+// a test run holds the board of student, keep it short
+pub const MAX_TEST_STEPS: usize = 1000;
+pub const MAX_STEP_DELAY_MS: u64 = 10 * 1000;
+pub const MAX_TEST_DURATION_MS: u64 = 5 * 60 * 1000;
+
+/// One step of a test script, actions are applied first, then expected IO is
+/// checked after delay
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestStep {
+    pub output: Option<IOSetting>,
+    // enable user clock with frequency, or disable it with 0
+    pub clock: Option<ClockSetting>,
+    // milliseconds to wait before checking
+    pub delay: u64,
+    pub expected: Option<IOSetting>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestScript {
+    pub direction: IOSetting,
+    pub steps: Vec<TestStep>,
+}
+
+impl TestScript {
+    /// Reject scripts that would hold the board for too long
+    pub fn check(&self) -> Result<(), String> {
+        if self.steps.len() > MAX_TEST_STEPS {
+            return Err(format!(
+                "test script has more than {} steps",
+                MAX_TEST_STEPS
+            ));
+        }
+        if let Some(step) = self
+            .steps
+            .iter()
+            .position(|step| step.delay > MAX_STEP_DELAY_MS)
+        {
+            return Err(format!(
+                "delay of step {} exceeds {} ms",
+                step, MAX_STEP_DELAY_MS
+            ));
+        }
+        let total: u64 = self.steps.iter().map(|step| step.delay).sum();
+        if total > MAX_TEST_DURATION_MS {
+            return Err(format!(
+                "test script runs longer than {} ms",
+                MAX_TEST_DURATION_MS
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestStepResult {
+    pub step: usize,
+    pub passed: bool,
+    // last reported IO when checked
+    pub actual: Option<IOSetting>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestReport {
+    pub passed: bool,
+    // set if aborted before all steps are run
+    pub message: Option<String>,
+    pub steps: Vec<TestStepResult>,
+}
+
+/// Test script running on a board session
+pub struct TestRun {
+    pub id: i32,
+    pub script: TestScript,
+    // None when programming
+    pub step: Option<usize>,
+    pub last_io: Option<IOSetting>,
+    pub results: Vec<TestStepResult>,
+}
+
+impl TestRun {
+    pub fn new(id: i32, script: TestScript) -> Self {
+        Self {
+            id,
+            script,
+            step: None,
+            last_io: None,
+            results: vec![],
+        }
+    }
+
+    /// Merge reported IO, board may only report changed bits
+    pub fn record_io(&mut self, io: &IOSetting) {
+        let data = match &io.data {
+            Some(data) => data.as_bytes(),
+            None => return,
+        };
+        let mut last: Vec<u8> = self
+            .last_io
+            .as_ref()
+            .and_then(|io| io.data.as_ref())
+            .map(|data| data.as_bytes().to_vec())
+            .unwrap_or_default();
+        if last.len() < data.len() {
+            last.resize(data.len(), b'0');
+        }
+        for (i, bit) in data.iter().enumerate() {
+            let masked = match &io.mask {
+                Some(mask) => mask.as_bytes().get(i) == Some(&b'1'),
+                None => true,
+            };
+            if masked {
+                last[i] = *bit;
+            }
+        }
+        self.last_io = Some(IOSetting {
+            mask: None,
+            data: Some(String::from_utf8_lossy(&last).into_owned()),
+        });
+    }
+
+    pub fn report(self, message: Option<String>) -> TestReport {
+        TestReport {
+            passed: message.is_none()
+                && self.results.len() == self.script.steps.len()
+                && self.results.iter().all(|result| result.passed),
+            message,
+            steps: self.results,
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn check_limits() {
+        use super::*;
+        let script = |steps: usize, delay: u64| TestScript {
+            direction: IOSetting {
+                mask: None,
+                data: None,
+            },
+            steps: vec![
+                TestStep {
+                    output: None,
+                    clock: None,
+                    delay,
+                    expected: None,
+                };
+                steps
+            ],
+        };
+        assert!(script(10, 100).check().is_ok());
+        assert!(script(MAX_TEST_STEPS + 1, 0).check().is_err());
+        assert!(script(1, MAX_STEP_DELAY_MS + 1).check().is_err());
+        assert!(script(MAX_TEST_STEPS, MAX_STEP_DELAY_MS).check().is_err());
+    }
+}
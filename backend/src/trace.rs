@@ -0,0 +1,120 @@
+use crate::common::{download_s3, err, read_tar_file};
+use crate::io_trace::{signal_names, to_vcd, IOTraceEvent};
+use crate::models::*;
+use crate::schema::{io_traces, jobs};
+use crate::session::get_user;
+use crate::DbPool;
+use actix_session::Session;
+use actix_web::{get, web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct TraceListRequest {
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TraceInfo {
+    id: i32,
+    user_name: String,
+    board: String,
+    job_id: Option<i32>,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+}
+
+#[get("/")]
+async fn list_self(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    query: web::Query<TraceListRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        let offset = query.offset.unwrap_or(0);
+        let limit = query.limit.unwrap_or(5);
+        let traces = web::block(move || {
+            // events are left out, they can be large
+            io_traces::dsl::io_traces
+                .select((
+                    io_traces::dsl::id,
+                    io_traces::dsl::user_name,
+                    io_traces::dsl::board,
+                    io_traces::dsl::job_id,
+                    io_traces::dsl::started_at,
+                    io_traces::dsl::finished_at,
+                ))
+                .filter(io_traces::dsl::user_name.eq(user.user_name))
+                .order(io_traces::dsl::id.desc())
+                .offset(offset)
+                .limit(limit)
+                .load::<(
+                    i32,
+                    String,
+                    String,
+                    Option<i32>,
+                    DateTime<Utc>,
+                    DateTime<Utc>,
+                )>(&conn)
+        })
+        .await
+        .map_err(err)?;
+        return Ok(HttpResponse::Ok().json(
+            traces
+                .into_iter()
+                .map(
+                    |(id, user_name, board, job_id, started_at, finished_at)| TraceInfo {
+                        id,
+                        user_name,
+                        board,
+                        job_id,
+                        started_at,
+                        finished_at,
+                    },
+                )
+                .collect::<Vec<TraceInfo>>(),
+        ));
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[get("/{trace_id}/vcd")]
+async fn vcd(sess: Session, pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if let Ok(trace) = io_traces::dsl::io_traces
+            .find(*path)
+            .first::<IoTrace>(&conn)
+        {
+            if user.is_staff() || user.user_name == trace.user_name {
+                let events: Vec<IOTraceEvent> = serde_json::from_str(&trace.events).map_err(err)?;
+                let job = match trace.job_id {
+                    Some(job_id) => jobs::dsl::jobs.find(job_id).first::<Job>(&conn).ok(),
+                    None => None,
+                };
+                let names = match job {
+                    Some(job) => {
+                        // constraints are in the source archive
+                        let constraints = download_s3(job.source)
+                            .await
+                            .and_then(|source| read_tar_file(&source, ".qsf"));
+                        signal_names(constraints.as_deref())
+                    }
+                    None => Default::default(),
+                };
+                let vcd = to_vcd(trace.started_at, &events, &names);
+                return Ok(HttpResponse::Ok()
+                    .content_type("text/plain")
+                    .header(
+                        "Content-Disposition",
+                        format!("attachment; filename=\"trace_{}.vcd\"", trace.id),
+                    )
+                    .body(vcd));
+            }
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
@@ -1,8 +1,8 @@
 use crate::common::err;
 use crate::models::*;
+use crate::presence::{get_presence, GetSessions, SessionInfo};
 use crate::schema::users::dsl;
 use crate::session::{get_user, hash_password};
-use crate::ws_user::ONLINE_USERS;
 use crate::DbPool;
 use actix_session::Session;
 use actix_web::{delete, get, post, put, web, HttpResponse, Result};
@@ -11,6 +11,7 @@ use diesel::prelude::*;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize)]
 struct UserListRequest {
@@ -35,6 +36,7 @@ struct UserInfo {
     role: String,
     last_login: Option<DateTime<Utc>>,
     session_count: Option<usize>,
+    sessions: Option<Vec<SessionInfo>>,
 }
 
 #[get("/list")]
@@ -58,12 +60,16 @@ async fn list(
             .await
             .map_err(err)?;
             let mut res = vec![];
-            let online_users = ONLINE_USERS.lock().unwrap().clone();
+            let sessions = get_presence()
+                .send(GetSessions { user_name: None })
+                .await
+                .map_err(err)?;
+            let mut session_counts = HashMap::new();
+            for session in sessions.0 {
+                *session_counts.entry(session.user_name).or_insert(0) += 1;
+            }
             for user in users {
-                let session_count = online_users
-                    .iter()
-                    .filter(|u| *u == &user.user_name)
-                    .count();
+                let session_count = session_counts.get(&user.user_name).cloned().unwrap_or(0);
                 res.push(UserInfo {
                     id: user.id,
                     user_name: user.user_name,
@@ -73,6 +79,7 @@ async fn list(
                     role: user.role,
                     last_login: user.last_login,
                     session_count: Some(session_count),
+                    sessions: None,
                 });
             }
             return Ok(HttpResponse::Ok().json(UserListResponse {
@@ -210,6 +217,12 @@ async fn get(
                 .filter(dsl::user_name.eq(&*path))
                 .first::<User>(&conn)
             {
+                let sessions = get_presence()
+                    .send(GetSessions {
+                        user_name: Some(user.user_name.clone()),
+                    })
+                    .await
+                    .map_err(err)?;
                 return Ok(HttpResponse::Ok().json(UserInfo {
                     id: user.id,
                     user_name: user.user_name,
@@ -218,7 +231,8 @@ async fn get(
                     student_id: user.student_id,
                     role: user.role,
                     last_login: user.last_login,
-                    session_count: None,
+                    session_count: Some(sessions.0.len()),
+                    sessions: Some(sessions.0),
                 }));
             } else {
                 return Ok(HttpResponse::Ok().json(false));
@@ -0,0 +1,171 @@
+use crate::common::{err, generate_uuid};
+use crate::job_status::JobStatus;
+use crate::models::*;
+use crate::schema::{build_workers, jobs};
+use crate::session::get_user;
+use crate::{DbConnection, DbPool};
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+
+// only the hash of token is stored
+fn hash_token(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+/// Find enabled build worker by token, and mark it as seen
+pub fn authenticate_worker(conn: &DbConnection, token: &str) -> QueryResult<Option<BuildWorker>> {
+    let worker = build_workers::dsl::build_workers
+        .filter(build_workers::dsl::token_hash.eq(hash_token(token)))
+        .filter(build_workers::dsl::enabled.eq(true))
+        .first::<BuildWorker>(conn)
+        .optional()?;
+    if let Some(worker) = &worker {
+        diesel::update(worker)
+            .set(build_workers::dsl::last_seen.eq(Some(Utc::now())))
+            .execute(conn)?;
+    }
+    Ok(worker)
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkerInfo {
+    id: i32,
+    name: String,
+    enabled: bool,
+    created_at: DateTime<Utc>,
+    last_seen: Option<DateTime<Utc>>,
+    // finished jobs reported by this worker
+    completed: i64,
+}
+
+#[get("/")]
+async fn list(sess: Session, pool: web::Data<DbPool>) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let workers = web::block(move || -> QueryResult<Vec<WorkerInfo>> {
+                let workers = build_workers::dsl::build_workers
+                    .order(build_workers::dsl::id)
+                    .load::<BuildWorker>(&conn)?;
+                let mut res = vec![];
+                for worker in workers {
+                    let completed = jobs::dsl::jobs
+                        .filter(jobs::dsl::worker_id.eq(worker.id))
+                        .filter(
+                            jobs::dsl::status.ne_all(vec![JobStatus::Queued, JobStatus::Running]),
+                        )
+                        .count()
+                        .get_result::<i64>(&conn)?;
+                    res.push(WorkerInfo {
+                        id: worker.id,
+                        name: worker.name,
+                        enabled: worker.enabled,
+                        created_at: worker.created_at,
+                        last_seen: worker.last_seen,
+                        completed,
+                    });
+                }
+                Ok(res)
+            })
+            .await
+            .map_err(err)?;
+            return Ok(HttpResponse::Ok().json(workers));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkerCreateRequest {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkerToken {
+    id: i32,
+    name: String,
+    token: String,
+}
+
+/// Register a build worker, the token is only returned here
+#[post("/")]
+async fn create(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    body: web::Json<WorkerCreateRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let token = generate_uuid();
+            let new_worker = NewBuildWorker {
+                name: body.name.clone(),
+                token_hash: hash_token(&token),
+            };
+            return match diesel::insert_into(build_workers::table)
+                .values(&new_worker)
+                .returning(build_workers::dsl::id)
+                .get_result::<i32>(&conn)
+            {
+                Ok(id) => Ok(HttpResponse::Ok().json(WorkerToken {
+                    id,
+                    name: new_worker.name,
+                    token,
+                })),
+                // name is taken
+                Err(_) => Ok(HttpResponse::BadRequest().json("worker exists")),
+            };
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkerUpdateRequest {
+    enabled: Option<bool>,
+    #[serde(default)]
+    reset_token: bool,
+}
+
+/// Enable or disable a worker, returns the new token if reset
+#[post("/{worker_id}")]
+async fn update(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    body: web::Json<WorkerUpdateRequest>,
+) -> Result<HttpResponse> {
+    let conn = pool.get().map_err(err)?;
+    if let (Some(user), conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            let worker = match build_workers::dsl::build_workers
+                .find(*path)
+                .first::<BuildWorker>(&conn)
+            {
+                Ok(worker) => worker,
+                Err(_) => return Ok(HttpResponse::NotFound().finish()),
+            };
+            if let Some(enabled) = body.enabled {
+                diesel::update(&worker)
+                    .set(build_workers::dsl::enabled.eq(enabled))
+                    .execute(&conn)
+                    .map_err(err)?;
+            }
+            let mut token = None;
+            if body.reset_token {
+                let new_token = generate_uuid();
+                diesel::update(&worker)
+                    .set(build_workers::dsl::token_hash.eq(hash_token(&new_token)))
+                    .execute(&conn)
+                    .map_err(err)?;
+                token = Some(new_token);
+            }
+            return Ok(HttpResponse::Ok().json(token));
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
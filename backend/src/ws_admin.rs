@@ -0,0 +1,213 @@
+use crate::board_manager::BoardInfo;
+use crate::job_status::JobStatus;
+use crate::session::get_user;
+use crate::DbPool;
+use actix::prelude::*;
+use actix_session::Session;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use log::*;
+use serde_derive::Serialize;
+use serde_json;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Debug, Clone)]
+pub enum AdminEvent {
+    BoardRegistered(BoardInfo),
+    BoardLeft(BoardInfo),
+    BoardMaintenance {
+        board: String,
+        reason: Option<String>,
+    },
+    BoardAllocated {
+        user_name: String,
+        board: String,
+    },
+    BoardReleased {
+        user_name: String,
+        board: String,
+    },
+    UserKicked {
+        user_name: String,
+        reason: String,
+    },
+    UserOnline {
+        user_name: String,
+        remote: String,
+    },
+    UserOffline {
+        user_name: String,
+        remote: String,
+    },
+    JobSubmitted {
+        id: i32,
+        submitter: String,
+    },
+    JobRestarted {
+        id: i32,
+        task_id: String,
+    },
+    JobFinished {
+        id: i32,
+        submitter: String,
+        status: JobStatus,
+    },
+}
+
+/// Fans out admin events to all ws_admin connections
+#[derive(Default)]
+pub struct AdminEventActor {
+    subscribers: Vec<Addr<WSAdmin>>,
+}
+
+impl actix::Supervised for AdminEventActor {}
+
+impl SystemService for AdminEventActor {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("admin event service is up");
+    }
+}
+
+impl Actor for AdminEventActor {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Subscribe(pub Addr<WSAdmin>);
+
+impl Handler<Subscribe> for AdminEventActor {
+    type Result = ();
+
+    fn handle(&mut self, req: Subscribe, _ctx: &mut Context<Self>) {
+        self.subscribers.push(req.0);
+    }
+}
+
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct Publish(pub AdminEvent);
+
+impl Handler<Publish> for AdminEventActor {
+    type Result = ();
+
+    fn handle(&mut self, req: Publish, _ctx: &mut Context<Self>) {
+        self.subscribers.retain(|addr| addr.connected());
+        for addr in &self.subscribers {
+            addr.do_send(req.clone());
+        }
+    }
+}
+
+pub fn publish(event: AdminEvent) {
+    AdminEventActor::from_registry().do_send(Publish(event));
+}
+
+pub struct WSAdmin {
+    user_name: String,
+    remote: String,
+    last_heartbeat: Instant,
+}
+
+impl Actor for WSAdmin {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(
+            "ws_admin client {} of {} goes online",
+            self.remote, self.user_name
+        );
+        AdminEventActor::from_registry().do_send(Subscribe(ctx.address()));
+        ctx.run_interval(Duration::from_secs(5), |actor, ctx| {
+            if Instant::now().duration_since(actor.last_heartbeat) > Duration::from_secs(30) {
+                warn!("ws_admin client {} has no heartbeat", actor.remote);
+                ctx.stop();
+            } else {
+                ctx.ping(b"");
+            }
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!("ws_admin client {} goes offline", self.remote);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSAdmin {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                debug!("ws_admin client {} heartbeat", self.remote);
+                self.last_heartbeat = Instant::now();
+            }
+            // events only go one way
+            Ok(ws::Message::Text(_text)) => {}
+            Ok(ws::Message::Binary(_bin)) => {}
+            Ok(ws::Message::Close(_)) => {
+                info!("ws_admin client {} closed connection", self.remote);
+                ctx.stop();
+            }
+            _ => ctx.stop(),
+        }
+    }
+}
+
+impl Handler<Publish> for WSAdmin {
+    type Result = ();
+
+    fn handle(&mut self, req: Publish, ctx: &mut Self::Context) {
+        ctx.text(serde_json::to_string(&req.0).unwrap());
+    }
+}
+
+pub async fn ws_admin(
+    sess: Session,
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let conn = req.connection_info();
+    let remote = conn.realip_remote_addr();
+    let conn = pool.get().unwrap();
+    if let (Some(user), _conn) = get_user(&sess, conn).await? {
+        if user.role == "admin" {
+            return ws::start(
+                WSAdmin {
+                    user_name: user.user_name,
+                    remote: String::from(remote.unwrap_or("Unknown Remote")),
+                    last_heartbeat: Instant::now(),
+                },
+                &req,
+                stream,
+            );
+        }
+    }
+    Ok(HttpResponse::Forbidden().finish())
+}
+
+mod test {
+    #[test]
+    fn show_serialized() {
+        use super::*;
+        println!(
+            "{}",
+            serde_json::to_string(&AdminEvent::BoardAllocated {
+                user_name: String::from("user"),
+                board: String::from("127.0.0.1"),
+            })
+            .unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&AdminEvent::JobFinished {
+                id: 1234,
+                submitter: String::from("user"),
+                status: JobStatus::Success,
+            })
+            .unwrap()
+        );
+    }
+}
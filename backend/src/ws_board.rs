@@ -1,4 +1,6 @@
-use crate::board_manager::{get_board_manager, BoardInfo, RegisterBoard, RouteToUser};
+use crate::board_manager::{
+    get_board_manager, BoardInfo, RegisterBoard, ReportHeartbeat, RouteToUser,
+};
 use crate::common::{ClockSetting, IOSetting};
 use crate::env::ENV;
 use actix::prelude::*;
@@ -7,44 +9,54 @@ use actix_web_actors::ws;
 use log::*;
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
+use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 
 pub struct WSBoard {
+    // ip and port of the connection
+    address: String,
+    // identity of the board, assigned by board manager when registered
     remote: String,
     authenticated: bool,
     software_version: String,
     hardware_version: String,
     last_heartbeat: Instant,
+    last_ping: Instant,
 }
 
 impl Actor for WSBoard {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        info!("ws_board client {} goes online", self.remote);
+        info!("ws_board client {} goes online", self.address);
         ctx.run_interval(Duration::from_secs(20), |actor, ctx| {
             if Instant::now().duration_since(actor.last_heartbeat) > Duration::from_secs(60) {
-                warn!("ws_board client {} has no heartbeat", actor.remote);
+                warn!("ws_board client {} has no heartbeat", actor.address);
                 ctx.stop();
             } else {
+                actor.last_ping = Instant::now();
                 ctx.ping(b"");
             }
         });
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        info!("ws_board client {} goes offline", self.remote);
+        info!("ws_board client {} goes offline", self.address);
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AuthenticateArgs {
     password: String,
     software_version: String,
     hardware_version: String,
+    // stable identity like serial number or mac address, boards without it
+    // are identified by ip address
+    #[serde(default)]
+    serial: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WSBoardMessageB2S {
     Authenticate(AuthenticateArgs),
     ProgramBitstreamFinish(bool),
@@ -70,8 +82,12 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSBoard {
             }
             Ok(ws::Message::Pong(_)) => {
                 if self.authenticated {
-                    debug!("ws_board client {} heartbeat", self.remote);
+                    debug!("ws_board client {} heartbeat", self.address);
                     self.last_heartbeat = Instant::now();
+                    get_board_manager().do_send(ReportHeartbeat {
+                        remote: self.remote.clone(),
+                        rtt: self.last_heartbeat.duration_since(self.last_ping),
+                    });
                 }
             }
             Ok(ws::Message::Text(text)) => match serde_json::from_str::<WSBoardMessageB2S>(&text) {
@@ -83,19 +99,23 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSBoard {
                                 self.authenticated = true;
                                 self.software_version = auth.software_version;
                                 self.hardware_version = auth.hardware_version;
-                                info!("ws_board client {} is authenticated", self.remote);
-                                get_board_manager().do_send(RegisterBoard {
+                                info!("ws_board client {} is authenticated", self.address);
+                                let register = get_board_manager().send(RegisterBoard {
                                     addr: ctx.address(),
-                                    info: BoardInfo {
-                                        remote: self.remote.clone(),
-                                        software_version: self.software_version.clone(),
-                                        hardware_version: self.hardware_version.clone(),
-                                    },
+                                    info: self.info(),
+                                    serial: auth.serial.filter(|serial| !serial.is_empty()),
                                 });
+                                let wrapped = actix::fut::wrap_future::<_, Self>(register);
+                                let then = wrapped.map(|res, actor, ctx| match res {
+                                    Ok(remote) => actor.remote = remote,
+                                    Err(_) => ctx.stop(),
+                                });
+                                // later messages are routed with the identity
+                                ctx.wait(then);
                             } else {
                                 warn!(
                                     "ws_board client {} authentication failed, closing",
-                                    self.remote
+                                    self.address
                                 );
                                 ctx.stop();
                             }
@@ -104,18 +124,14 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSBoard {
                     _ if !self.authenticated => {
                         warn!(
                             "ws_board client {} did not authenticate, closing",
-                            self.remote
+                            self.address
                         );
                         ctx.stop();
                     }
                     _ => {
                         get_board_manager().do_send(RouteToUser {
                             addr: ctx.address(),
-                            info: BoardInfo {
-                                remote: self.remote.clone(),
-                                software_version: self.software_version.clone(),
-                                hardware_version: self.hardware_version.clone(),
-                            },
+                            info: self.info(),
                             action: msg,
                         });
                     }
@@ -123,14 +139,14 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSBoard {
                 Err(_err) => {
                     warn!(
                         "ws_board client {} sent wrong message, closing",
-                        self.remote
+                        self.address
                     );
                     ctx.stop();
                 }
             },
             Ok(ws::Message::Binary(_bin)) => {}
             Ok(ws::Message::Close(_)) => {
-                info!("ws_board client {} closed connection", self.remote);
+                info!("ws_board client {} closed connection", self.address);
                 ctx.stop();
             }
             _ => ctx.stop(),
@@ -139,15 +155,39 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSBoard {
 }
 
 impl WSBoard {
-    fn new(remote: &str) -> Self {
+    fn new(address: &str) -> Self {
         Self {
-            remote: String::from(remote),
+            address: String::from(address),
+            remote: String::from(strip_port(address)),
             software_version: String::from("Unknown"),
             hardware_version: String::from("Unknown"),
             last_heartbeat: Instant::now(),
+            last_ping: Instant::now(),
             authenticated: false,
         }
     }
+
+    fn info(&self) -> BoardInfo {
+        BoardInfo {
+            remote: self.remote.clone(),
+            address: self.address.clone(),
+            software_version: self.software_version.clone(),
+            hardware_version: self.hardware_version.clone(),
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseBoard;
+
+impl Handler<CloseBoard> for WSBoard {
+    type Result = ();
+
+    fn handle(&mut self, _req: CloseBoard, ctx: &mut Self::Context) {
+        info!("ws_board client {} is replaced, closing", self.address);
+        ctx.stop();
+    }
 }
 
 #[derive(Message)]
@@ -178,17 +218,52 @@ impl Handler<ProgramBitstream> for WSBoard {
     }
 }
 
-pub async fn ws_board(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+// source port changes on every connection, keep the host only
+fn strip_port(addr: &str) -> &str {
+    match addr.parse::<SocketAddr>() {
+        Ok(_) => match addr.rfind(':') {
+            Some(pos) => addr[..pos].trim_start_matches('[').trim_end_matches(']'),
+            None => addr,
+        },
+        // bare ip, or host with port from forwarded headers
+        Err(_) if addr.parse::<IpAddr>().is_ok() => addr,
+        Err(_) => match addr.rfind(':') {
+            Some(pos) if addr[pos + 1..].parse::<u16>().is_ok() => &addr[..pos],
+            _ => addr,
+        },
+    }
+}
+
+/// Boards without serial are identified by remote address without port, so
+/// the same board gets the same key across reconnects
+pub fn board_remote(req: &HttpRequest) -> String {
     let conn = req.connection_info();
-    let remote = conn.realip_remote_addr();
-    ws::start(
-        WSBoard::new(remote.unwrap_or("Unknown Remote")),
-        &req,
-        stream,
-    )
+    String::from(strip_port(
+        conn.realip_remote_addr().unwrap_or("Unknown Remote"),
+    ))
+}
+
+pub async fn ws_board(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+    let address = String::from(
+        req.connection_info()
+            .realip_remote_addr()
+            .unwrap_or("Unknown Remote"),
+    );
+    ws::start(WSBoard::new(&address), &req, stream)
 }
 
 mod test {
+    #[test]
+    fn remote_without_port() {
+        use super::*;
+        assert_eq!(strip_port("10.0.0.1:43210"), "10.0.0.1");
+        assert_eq!(strip_port("10.0.0.1"), "10.0.0.1");
+        assert_eq!(strip_port("[::1]:43210"), "::1");
+        assert_eq!(strip_port("::1"), "::1");
+        assert_eq!(strip_port("board.lab:8080"), "board.lab");
+        assert_eq!(strip_port("Unknown Remote"), "Unknown Remote");
+    }
+
     #[test]
     fn show_serialized() {
         use super::*;
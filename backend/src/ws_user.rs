@@ -1,11 +1,21 @@
 use crate::board_manager::{
-    get_board_manager, ProgramBitstreamToBoard, RequestForBoard, RouteToBoard,
+    get_board_manager, ApproveTakeover, EndTakeover, InviteMember, JoinSession, LeaveSession,
+    PassControl, ProgramBitstreamToBoard, RequestForBoard, RequestTakeover, RouteToBoard,
+    SetControlToken, SetIORecording, UnwatchBoard, WatchBoard,
 };
-use crate::common::{download_s3, IOSetting};
+use crate::build_log::{get_build_log, job_log, SubscribeJobLog, STREAMS};
+use crate::common::{download_s3, generate_uuid, IOSetting};
 use crate::env::ENV;
+use crate::help_queue::{
+    get_help_queue, ClaimHelp, HelpEntry, HelpMode, RaiseHand, ResolveHelp, WatchHelpQueue,
+};
+use crate::job_status::JobStatus;
 use crate::models::*;
+use crate::presence::{get_presence, Connect, Disconnect, SetBoard};
 use crate::schema::jobs;
 use crate::session::get_user;
+use crate::task::JobInfo;
+use crate::test_vector::TestReport;
 use crate::ws_board::{WSBoardMessageB2S, WSBoardMessageS2B};
 use crate::DbPool;
 use actix::prelude::*;
@@ -14,22 +24,20 @@ use actix_session::Session;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use diesel::prelude::*;
-use lazy_static::lazy_static;
 use log::*;
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
-use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
-lazy_static! {
-    pub static ref ONLINE_USERS: Mutex<Vec<String>> = Mutex::new(vec![]);
-}
-
 pub struct WSUser {
+    id: String,
     user_name: String,
+    role: String,
+    class: Option<String>,
     remote: String,
     last_heartbeat: Instant,
     has_board: bool,
+    watching: bool,
 
     text_buffer: Option<Vec<u8>>,
     pool: DbPool,
@@ -40,7 +48,12 @@ impl Actor for WSUser {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         info!("ws_user client {} goes online", self.remote);
-        ONLINE_USERS.lock().unwrap().push(self.user_name.clone());
+        get_presence().do_send(Connect {
+            id: self.id.clone(),
+            user_name: self.user_name.clone(),
+            remote: self.remote.clone(),
+            addr: ctx.address(),
+        });
         ctx.run_interval(Duration::from_secs(5), |actor, ctx| {
             if Instant::now().duration_since(actor.last_heartbeat) > Duration::from_secs(30) {
                 warn!("ws_user client {} has no heartbeat", actor.remote);
@@ -53,10 +66,10 @@ impl Actor for WSUser {
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         info!("ws_user client {} goes offline", self.remote);
-        let mut users = ONLINE_USERS.lock().unwrap();
-        if let Some(index) = users.iter().position(|x| *x == self.user_name) {
-            users.remove(index);
-        }
+        get_presence().do_send(Disconnect {
+            id: self.id.clone(),
+            user_name: self.user_name.clone(),
+        });
     }
 }
 
@@ -65,14 +78,65 @@ pub enum WSUserMessageU2S {
     RequestForBoard(String),
     ToBoard(WSBoardMessageS2B),
     ProgramBitstream(i32),
+    WatchBoard(String),
+    UnwatchBoard(String),
+    InviteMember(String),
+    JoinSession(String),
+    LeaveSession(String),
+    SetControlToken(bool),
+    PassControl(String),
+    RequestTakeover(String),
+    ApproveTakeover(bool),
+    EndTakeover(String),
+    RaiseHand {
+        note: String,
+        job_id: Option<i32>,
+        board: Option<String>,
+    },
+    // lower own hand if None
+    ResolveHelp(Option<u64>),
+    ClaimHelp {
+        id: u64,
+        mode: Option<HelpMode>,
+    },
+    // all classes if None
+    WatchHelpQueue(Option<String>),
+    RecordIOTrace(bool),
+    SubscribeJobLog(i32),
+    UnsubscribeJobLog(i32),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum WSUserMessageS2U {
     ReportIOChange(IOSetting),
     BoardAllocateResult(Option<String>),
     BoardDisconnected(String),
     ProgramBitstreamFinish(bool),
+    Kicked(String),
+    WatchResult(Option<String>),
+    SpectatorJoined(String),
+    SpectatorLeft(String),
+    SessionInvitation(String),
+    MemberJoined(String),
+    MemberLeft(String),
+    ControlChanged(Option<String>),
+    TakeoverRequested(String),
+    TakeoverResult(Option<String>),
+    TakeoverChanged(Option<String>),
+    HelpStatus(Option<HelpEntry>),
+    HelpQueue(Vec<HelpEntry>),
+    IORecording(bool),
+    IOTraceSaved(i32),
+    TestRunFinished {
+        id: i32,
+        report: TestReport,
+    },
+    JobUpdate(JobInfo),
+    JobLog {
+        job_id: i32,
+        stream: String,
+        data: String,
+    },
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSUser {
@@ -124,10 +188,88 @@ impl Handler<RequestForBoardResult> for WSUser {
 
     fn handle(&mut self, req: RequestForBoardResult, ctx: &mut Self::Context) -> () {
         self.has_board = req.0.is_some();
+        get_presence().do_send(SetBoard {
+            id: self.id.clone(),
+            user_name: self.user_name.clone(),
+            board: req.0.clone(),
+        });
         ctx.text(serde_json::to_string(&WSUserMessageS2U::BoardAllocateResult(req.0)).unwrap());
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchBoardResult(pub Option<String>);
+
+impl Handler<WatchBoardResult> for WSUser {
+    type Result = ();
+
+    fn handle(&mut self, req: WatchBoardResult, ctx: &mut Self::Context) -> () {
+        self.watching = req.0.is_some();
+        ctx.text(serde_json::to_string(&WSUserMessageS2U::WatchResult(req.0)).unwrap());
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TakeoverResult(pub Option<String>);
+
+impl Handler<TakeoverResult> for WSUser {
+    type Result = ();
+
+    fn handle(&mut self, req: TakeoverResult, ctx: &mut Self::Context) -> () {
+        self.has_board = req.0.is_some();
+        get_presence().do_send(SetBoard {
+            id: self.id.clone(),
+            user_name: self.user_name.clone(),
+            board: req.0.clone(),
+        });
+        ctx.text(serde_json::to_string(&WSUserMessageS2U::TakeoverResult(req.0)).unwrap());
+    }
+}
+
+/// Staff claimed a help entry and wants to see the board of the student
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartHelp {
+    pub target: String,
+    pub mode: HelpMode,
+}
+
+impl Handler<StartHelp> for WSUser {
+    type Result = ();
+
+    fn handle(&mut self, req: StartHelp, ctx: &mut Self::Context) {
+        if self.has_board || self.watching || !self.is_staff() {
+            return;
+        }
+        match req.mode {
+            HelpMode::Watch => get_board_manager().do_send(WatchBoard {
+                user: ctx.address(),
+                user_name: self.user_name.clone(),
+                target: req.target,
+            }),
+            HelpMode::Takeover => get_board_manager().do_send(RequestTakeover {
+                user: ctx.address(),
+                user_name: self.user_name.clone(),
+                target: req.target,
+            }),
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NotifyUser(pub WSUserMessageS2U);
+
+impl Handler<NotifyUser> for WSUser {
+    type Result = ();
+
+    fn handle(&mut self, req: NotifyUser, ctx: &mut Self::Context) {
+        ctx.text(serde_json::to_string(&req.0).unwrap());
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SendToUser {
@@ -151,30 +293,58 @@ impl Handler<BoardDisconnected> for WSUser {
 
     fn handle(&mut self, _req: BoardDisconnected, ctx: &mut Self::Context) {
         self.has_board = false;
+        self.watching = false;
+        get_presence().do_send(SetBoard {
+            id: self.id.clone(),
+            user_name: self.user_name.clone(),
+            board: None,
+        });
         ctx.text(
             serde_json::to_string(&WSUserMessageS2U::BoardDisconnected(String::from(""))).unwrap(),
         );
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick(pub String);
+
+impl Handler<Kick> for WSUser {
+    type Result = ();
+
+    fn handle(&mut self, req: Kick, ctx: &mut Self::Context) {
+        info!("ws_user client {} kicked: {}", self.remote, req.0);
+        ctx.text(serde_json::to_string(&WSUserMessageS2U::Kicked(req.0)).unwrap());
+        ctx.stop();
+    }
+}
+
 impl WSUser {
-    fn new(remote: &str, user_name: &str, pool: DbPool) -> Self {
+    fn new(remote: &str, user_name: &str, role: &str, class: Option<String>, pool: DbPool) -> Self {
         Self {
+            id: generate_uuid(),
             remote: String::from(remote),
             user_name: String::from(user_name),
+            role: String::from(role),
+            class,
             last_heartbeat: Instant::now(),
             has_board: false,
+            watching: false,
 
             text_buffer: None,
             pool,
         }
     }
 
+    fn is_staff(&self) -> bool {
+        is_staff_role(&self.role)
+    }
+
     fn handle_message(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
         match serde_json::from_str::<WSUserMessageU2S>(text) {
             Ok(msg) => match msg {
                 WSUserMessageU2S::RequestForBoard(hint) => {
-                    if !self.has_board {
+                    if !self.has_board && !self.watching {
                         get_board_manager().do_send(RequestForBoard {
                             user: ctx.address(),
                             user_name: self.user_name.clone(),
@@ -195,19 +365,24 @@ impl WSUser {
                     let mut fail = true;
                     if let Ok(conn) = self.pool.get() {
                         if let Ok(job) = jobs::dsl::jobs.find(job_id).first::<Job>(&conn) {
-                            if job.status.is_some()
+                            // staff can program jobs of others, board manager
+                            // checks that they are taking over the board
+                            if job.status == JobStatus::Success
                                 && job.destination.is_some()
-                                && job.submitter == self.user_name
+                                && (job.submitter == self.user_name || self.is_staff())
                             {
                                 // job is done
                                 fail = false;
                                 let download = download_s3(job.destination.unwrap().clone());
+                                let submitter = job.submitter;
                                 let wrapped = actix::fut::wrap_future::<_, Self>(download);
-                                let then = wrapped.map(|res, actor, ctx| {
+                                let then = wrapped.map(move |res, actor, ctx| {
                                     if let Some(data) = res {
                                         get_board_manager().do_send(ProgramBitstreamToBoard {
                                             user: ctx.address(),
                                             user_name: actor.user_name.clone(),
+                                            job_id,
+                                            submitter,
                                             data,
                                         });
                                     } else {
@@ -231,6 +406,196 @@ impl WSUser {
                         );
                     }
                 }
+                WSUserMessageU2S::WatchBoard(target) => {
+                    // only staff can watch other's board
+                    if !self.has_board && !self.watching && self.is_staff() {
+                        get_board_manager().do_send(WatchBoard {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                            target,
+                        });
+                    } else {
+                        ctx.text(
+                            serde_json::to_string(&WSUserMessageS2U::WatchResult(None)).unwrap(),
+                        );
+                    }
+                }
+                WSUserMessageU2S::UnwatchBoard(_) => {
+                    if self.watching {
+                        get_board_manager().do_send(UnwatchBoard {
+                            user: ctx.address(),
+                        });
+                    }
+                }
+                WSUserMessageU2S::InviteMember(member) => {
+                    if self.has_board {
+                        get_board_manager().do_send(InviteMember {
+                            user_name: self.user_name.clone(),
+                            member,
+                        });
+                    }
+                }
+                WSUserMessageU2S::JoinSession(owner) => {
+                    if !self.has_board && !self.watching {
+                        get_board_manager().do_send(JoinSession {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                            owner,
+                        });
+                    }
+                }
+                WSUserMessageU2S::LeaveSession(_) => {
+                    if self.has_board {
+                        get_board_manager().do_send(LeaveSession {
+                            user: ctx.address(),
+                        });
+                    }
+                }
+                WSUserMessageU2S::SetControlToken(enabled) => {
+                    if self.has_board {
+                        get_board_manager().do_send(SetControlToken {
+                            user_name: self.user_name.clone(),
+                            enabled,
+                        });
+                    }
+                }
+                WSUserMessageU2S::PassControl(to) => {
+                    if self.has_board {
+                        get_board_manager().do_send(PassControl {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                            to,
+                        });
+                    }
+                }
+                WSUserMessageU2S::RequestTakeover(target) => {
+                    if !self.has_board && !self.watching && self.is_staff() {
+                        get_board_manager().do_send(RequestTakeover {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                            target,
+                        });
+                    } else {
+                        ctx.text(
+                            serde_json::to_string(&WSUserMessageS2U::TakeoverResult(None)).unwrap(),
+                        );
+                    }
+                }
+                WSUserMessageU2S::ApproveTakeover(approve) => {
+                    if self.has_board {
+                        get_board_manager().do_send(ApproveTakeover {
+                            user_name: self.user_name.clone(),
+                            approve,
+                        });
+                    }
+                }
+                WSUserMessageU2S::RaiseHand {
+                    note,
+                    job_id,
+                    board,
+                } => {
+                    let mut valid = true;
+                    if let Some(job_id) = job_id {
+                        // only own jobs can be attached
+                        valid = false;
+                        if let Ok(conn) = self.pool.get() {
+                            if let Ok(job) = jobs::dsl::jobs.find(job_id).first::<Job>(&conn) {
+                                valid = job.submitter == self.user_name;
+                            }
+                        }
+                    }
+                    if valid {
+                        get_help_queue().do_send(RaiseHand {
+                            user_name: self.user_name.clone(),
+                            class: self.class.clone(),
+                            note,
+                            job_id,
+                            board,
+                        });
+                    } else {
+                        info!("raise hand rejected by wrong job id");
+                    }
+                }
+                WSUserMessageU2S::ResolveHelp(id) => {
+                    get_help_queue().do_send(ResolveHelp {
+                        user_name: self.user_name.clone(),
+                        is_staff: self.is_staff(),
+                        id,
+                    });
+                }
+                WSUserMessageU2S::ClaimHelp { id, mode } => {
+                    if self.is_staff() {
+                        get_help_queue().do_send(ClaimHelp {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                            id,
+                            mode,
+                        });
+                    }
+                }
+                WSUserMessageU2S::WatchHelpQueue(class) => {
+                    if self.is_staff() {
+                        get_help_queue().do_send(WatchHelpQueue {
+                            user: ctx.address(),
+                            class,
+                        });
+                    }
+                }
+                WSUserMessageU2S::RecordIOTrace(enabled) => {
+                    if self.has_board {
+                        get_board_manager().do_send(SetIORecording {
+                            user_name: self.user_name.clone(),
+                            enabled,
+                        });
+                    }
+                }
+                WSUserMessageU2S::SubscribeJobLog(job_id) => {
+                    if let Ok(conn) = self.pool.get() {
+                        if let Ok(job) = jobs::dsl::jobs.find(job_id).first::<Job>(&conn) {
+                            if job.submitter == self.user_name || self.is_staff() {
+                                // send what is built so far, then follow new chunks
+                                if let Ok(mut log) = job_log(&conn, &job) {
+                                    for stream in STREAMS.iter() {
+                                        let data = log.remove(*stream).unwrap_or_default();
+                                        if data.is_empty() {
+                                            continue;
+                                        }
+                                        let msg = WSUserMessageS2U::JobLog {
+                                            job_id,
+                                            stream: String::from(*stream),
+                                            data,
+                                        };
+                                        if let Ok(text) = serde_json::to_string(&msg) {
+                                            ctx.text(text);
+                                        }
+                                    }
+                                }
+                                if !job.status.is_finished() {
+                                    get_build_log().do_send(SubscribeJobLog {
+                                        job_id,
+                                        user: ctx.address(),
+                                        subscribe: true,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+                WSUserMessageU2S::UnsubscribeJobLog(job_id) => {
+                    get_build_log().do_send(SubscribeJobLog {
+                        job_id,
+                        user: ctx.address(),
+                        subscribe: false,
+                    });
+                }
+                WSUserMessageU2S::EndTakeover(_) => {
+                    if self.has_board {
+                        get_board_manager().do_send(EndTakeover {
+                            user: ctx.address(),
+                            user_name: self.user_name.clone(),
+                        });
+                    }
+                }
             },
             Err(_err) => {
                 warn!("ws_user client {} sent wrong message, closing", self.remote);
@@ -254,6 +619,8 @@ pub async fn ws_user(
             WSUser::new(
                 remote.unwrap_or("Unknown Remote"),
                 &user.user_name,
+                &user.role,
+                user.class,
                 pool.get_ref().clone(),
             ),
             &req,
@@ -266,6 +633,8 @@ pub async fn ws_user(
             WSUser::new(
                 remote.unwrap_or("Unknown Remote"),
                 &format!("Anonymous-{:?}", remote),
+                "user",
+                None,
                 pool.get_ref().clone(),
             ),
             &req,
@@ -323,5 +692,55 @@ mod test {
             "{}",
             serde_json::to_string(&WSUserMessageS2U::BoardDisconnected(String::from(""))).unwrap()
         );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageS2U::Kicked(String::from("too many sessions")))
+                .unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::WatchBoard(String::from("student"))).unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageS2U::SpectatorJoined(String::from("ta"))).unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::JoinSession(String::from("owner"))).unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::RequestTakeover(String::from("student")))
+                .unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::ApproveTakeover(true)).unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::RaiseHand {
+                note: String::from("board not responding"),
+                job_id: Some(1234),
+                board: None,
+            })
+            .unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageU2S::ClaimHelp {
+                id: 1,
+                mode: Some(HelpMode::Watch),
+            })
+            .unwrap()
+        );
+        println!(
+            "{}",
+            serde_json::to_string(&WSUserMessageS2U::ControlChanged(Some(String::from(
+                "member"
+            ))))
+            .unwrap()
+        );
     }
 }
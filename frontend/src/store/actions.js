@@ -193,6 +193,7 @@ export function login(user, pass) {
       if(!data.login) return false;
 
       dispatch(setUser(data));
+      dispatch(openUserSocket());
 
       await dispatch(initBuilds());
 
@@ -214,13 +215,7 @@ export function restore() {
       if (!data.login) return false;
 
       dispatch(setUser(data));
-      let websocket = connectWebSocket(dispatch);
-
-      dispatch(setBoard({
-        websocket,
-        ident: null,
-        status: BOARD_STATUS.DISCONNECTED,
-      }));
+      dispatch(openUserSocket());
 
       await dispatch(initBuilds());
 
@@ -234,9 +229,10 @@ export function restore() {
 
 export function logout() {
   // TODO: show blocker
-  return async dispatch => {
+  return async (dispatch, getState) => {
     try {
       await get('/api/session', 'DELETE');
+      getState().board?.websocket?.close();
       dispatch(setUser(null));
       dispatch(loadBuilds(IList()));
       return true;
@@ -346,11 +342,25 @@ export async function kickoffPolling(dispatch, getState) {
     return await kickoffPolling(dispatch, getState);
   }
 
-  await new Promise(resolve => setTimeout(resolve, BUILD_POLL_INTERVAL));
+  // Updates are pushed through websocket when connected, reopen it if lost
+  const pushed = getState().board?.websocket?.readyState === WebSocket.OPEN;
+  if(!pushed) dispatch(openUserSocket());
+  await new Promise(resolve => setTimeout(resolve, pushed ? BUILD_POLL_INTERVAL * 10 : BUILD_POLL_INTERVAL));
   polling = false;
   return await kickoffPolling(dispatch, getState);
 }
 
+// Pushed by backend on status change, so polling only serves as a fallback
+function updateJob(info) {
+  return (dispatch, getState) => {
+    const current = getState().builds.list.find(e => e.id === info.id);
+    dispatch(putBuild(jobMapper(info)));
+
+    if(current && current.status === null && info.status)
+      dispatch(showSnackbar(<>Build #{info.id}<span className="sep">/</span>{info.status.toLowerCase()}</>));
+  }
+}
+
 export function refreshBuild(id) {
   return async dispatch => {
     const info = await get(`/api/task/get/${id}`);
@@ -464,6 +474,8 @@ function connectWebSocket(dispatch) {
       dispatch(updateInput(data));
     } else if (msg['ProgramBitstreamFinish'])
       dispatch(updateBoard(BOARD_STATUS.CONNECTED));
+    else if (msg['JobUpdate'])
+      dispatch(updateJob(msg['JobUpdate']));
   };
 
   websocket.onclose = () => {
@@ -476,6 +488,24 @@ function connectWebSocket(dispatch) {
   return websocket;
 }
 
+/**
+ * Open the user websocket without requesting a board, so job updates are pushed
+ * to every logged in user
+ */
+export function openUserSocket() {
+  return (dispatch, getState) => {
+    const { board } = getState();
+    const state = board?.websocket?.readyState;
+    if(state === WebSocket.OPEN || state === WebSocket.CONNECTING) return;
+
+    dispatch(setBoard({
+      websocket: connectWebSocket(dispatch),
+      ident: null,
+      status: BOARD_STATUS.DISCONNECTED,
+    }));
+  }
+}
+
 export function connectToBoard(target = null) {
   return async (dispatch, getState) => {
     // TODO: enter waiting state
@@ -488,6 +518,11 @@ export function connectToBoard(target = null) {
         websocket = board.websocket;
         if (websocket.readyState === WebSocket.OPEN) {
           websocket.send(`{"RequestForBoard":"${serialized}"}`);
+        } else if (websocket.readyState === WebSocket.CONNECTING) {
+          // User socket opened at login is still connecting
+          websocket.onopen = () => {
+            websocket.send(`{"RequestForBoard":"${serialized}"}`);
+          };
         } else {
           websocket.close();
           websocket = null;